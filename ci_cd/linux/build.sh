@@ -11,6 +11,8 @@ mkdir -p ${ARTIFACTSDIR}
 
 test -d ${PROJECTDIR}/rclip_config/target && rm -rf ${PROJECTDIR}/rclip_config/target
 test -d ${PROJECTDIR}/rclip_client/target && rm -rf ${PROJECTDIR}/rclip_client/target
+test -d ${PROJECTDIR}/rclip_client_lib/target && rm -rf ${PROJECTDIR}/rclip_client_lib/target
+test -d ${PROJECTDIR}/rclip_protocol/target && rm -rf ${PROJECTDIR}/rclip_protocol/target
 test -d ${PROJECTDIR}/rclip_server/target && rm -rf ${PROJECTDIR}/rclip_server/target
 
 cd ${PROJECTDIR}
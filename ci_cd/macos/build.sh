@@ -10,6 +10,8 @@ ARTIFACTSDIR="${PROJECTDIR}/artifacts/rclip-macos-amd64-${APPVERSION}"
 mkdir -p ${ARTIFACTSDIR}
 
 rm -rf ${PROJECTDIR}/rclip_client/target
+rm -rf ${PROJECTDIR}/rclip_client_lib/target
+rm -rf ${PROJECTDIR}/rclip_protocol/target
 rm -rf ${PROJECTDIR}/rclip_config/target
 rm -rf ${PROJECTDIR}/rclip_server/target
 
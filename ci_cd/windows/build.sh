@@ -13,6 +13,8 @@ cp ${PROJECTDIR}/LICENSE ${ARTIFACTSDIR}/LICENSE.txt
 
 rm -rf ${PROJECTDIR}/rclip_config/target
 rm -rf ${PROJECTDIR}/rclip_client/target
+rm -rf ${PROJECTDIR}/rclip_client_lib/target
+rm -rf ${PROJECTDIR}/rclip_protocol/target
 rm -rf ${PROJECTDIR}/rclip_server/target
 
 cd ${PROJECTDIR}
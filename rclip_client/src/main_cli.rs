@@ -1,11 +1,45 @@
-use clap::{App, Arg};
-use rclip_config;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rclip_client_lib::{ClientError, Command, RclipClient, DEFAULT_CONFIG_FILENAME_CLIENT};
 use std::error::Error;
+use std::io::{self, Write};
 use std::path::Path;
 
 mod common;
 
-fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+const SUBCOMMAND_PAIR: &str   = "pair";
+const SUBCOMMAND_IMPORT: &str = "import";
+const DEFAULT_CLIENT_NAME: &str = "rclip-client";
+
+// Exit codes by class of failure, 1 for anything else
+const EXIT_CONNECTION: i32 = 2;
+const EXIT_TRUST: i32      = 3;
+const EXIT_AUTH: i32       = 4;
+const EXIT_LIMIT: i32      = 5;
+const EXIT_PROTOCOL: i32   = 6;
+const EXIT_CLIPBOARD: i32  = 7;
+const EXIT_SERVER: i32     = 8;
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_code(e.as_ref()));
+    }
+}
+
+fn exit_code(error: &(dyn Error + Send + Sync + 'static)) -> i32 {
+    match error.downcast_ref::<ClientError>() {
+        Some(ClientError::Connection(_)) => EXIT_CONNECTION,
+        Some(ClientError::Trust(_)) => EXIT_TRUST,
+        Some(ClientError::Auth(_)) => EXIT_AUTH,
+        Some(ClientError::Limit(_)) => EXIT_LIMIT,
+        Some(ClientError::Protocol(_)) => EXIT_PROTOCOL,
+        Some(ClientError::Clipboard(_)) => EXIT_CLIPBOARD,
+        Some(ClientError::Server(_)) => EXIT_SERVER,
+        _ => 1,
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error + Send + Sync>> {
     let app = App::new(option_env!("CARGO_PKG_NAME").unwrap_or("Unknown"))
         .version(option_env!("CARGO_PKG_VERSION").unwrap_or("Unknown"))
         .author(option_env!("CARGO_PKG_AUTHORS").unwrap_or("Unknown"))
@@ -13,7 +47,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .arg(
             Arg::with_name("host")
                 .long("host")
-                .help("Server host")
+                .help("Server host (IPv6 literals may be bracketed, i.e. [::1]), vsock:CID, unix:PATH or device:PATH")
                 .required(false)
                 .takes_value(true),
         )
@@ -27,9 +61,9 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .arg(
             Arg::with_name("command")
                 .long("command")
-                .help("READ, WRITE or CLEAR")
+                .help("READ, WRITE, CLEAR or STATUS")
                 .required(false)
-                .possible_values(&["READ", "WRITE", "CLEAR"])
+                .possible_values(&["READ", "WRITE", "CLEAR", "STATUS"])
                 .default_value("READ")
                 .takes_value(true),
         )
@@ -43,22 +77,201 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .arg(
             Arg::with_name("der-cert-pub")
                 .long("der-cert-pub")
-                .help("Public DER certificate key")
+                .help("Public certificate or certificate chain (PEM or DER)")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fingerprint")
+                .long("fingerprint")
+                .help("SHA-256 fingerprint of the server certificate (sha256:...), can be repeated")
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trust-mode")
+                .long("trust-mode")
+                .help("Server certificate verification: the configured certificate only, trust on first use, or the standard verification against a CA bundle")
+                .required(false)
+                .possible_values(&[rclip_config::TRUST_MODE_PINNED, rclip_config::TRUST_MODE_TOFU, rclip_config::TRUST_MODE_CA])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("accept-new")
+                .long("accept-new")
+                .help("Trust unknown servers without confirmation and record their fingerprint (implies --trust-mode tofu)")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("ca-file")
+                .long("ca-file")
+                .help("CA certificates (PEM or DER) for the 'ca' trust mode, instead of the system roots")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("server-name")
+                .long("server-name")
+                .help("Name expected in the server certificate, if different from the host")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("client-cert")
+                .long("client-cert")
+                .help("Client certificate (PEM or DER), for servers requiring client certificates")
+                .required(false)
+                .requires("client-key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("client-key")
+                .long("client-key")
+                .help("Private key of the client certificate (PEM or DER)")
+                .required(false)
+                .requires("client-cert")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trust-next")
+                .long("trust-next")
+                .help("With the STATUS command, add the fingerprint of the next server certificate to the configuration")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .help("Client token, as issued by the server when pairing")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .help("Connect, read and write timeout in seconds, 0 for none")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .help("Number of retries after failing to reach the server")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("proxy-command")
+                .long("proxy-command")
+                .help("Command connected to the server on its standard input and output, %h and %p standing for the host and port, i.e. 'ssh jump nc %h %p'")
                 .required(false)
                 .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name(SUBCOMMAND_PAIR)
+                .about("Pairs with a server using the one-time code printed by 'rclip-server pair'")
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .help("Server host (IPv6 literals may be bracketed, i.e. [::1]), vsock:CID, unix:PATH or device:PATH")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .help("Server port")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("code")
+                        .long("code")
+                        .help("Pairing code")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .help("Name of this client on the server [default: host name]")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Replace the certificate of a previously paired server")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(SUBCOMMAND_IMPORT)
+                .about("Configures the client from a connection bundle, as exported by 'rclip-server export-client-bundle'")
+                .arg(
+                    Arg::with_name("bundle")
+                        .help("rclip:// URI or bundle file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Replace the certificate of a previously configured server")
+                        .required(false)
+                        .takes_value(false),
+                ),
         );
 
     let run_matches = app.to_owned().get_matches();
 
     let mut client_config =
-        match rclip_config::load_default_config(common::DEFAULT_CONFIG_FILENAME_CLIENT) {
+        match rclip_config::load_default_config(DEFAULT_CONFIG_FILENAME_CLIENT) {
             Ok(cfg) => cfg,
             Err(e) => {
-                eprintln!("Warn: Error parsing configuration file: {}!", e.to_string());
+                eprintln!("Warn: Error parsing configuration file: {}!", e);
                 rclip_config::ClientConfig::default()
             }
         };
 
+    // Connection settings also apply to pairing
+    if let Some(timeout) = run_matches.value_of("timeout") {
+        let timeout = timeout.parse::<u64>().map_err(|e| format!("Invalid timeout '{}'. {}", timeout, e))?;
+        client_config.connection.connect_timeout = Some(timeout);
+        client_config.connection.read_timeout = Some(timeout);
+        client_config.connection.write_timeout = Some(timeout);
+    }
+
+    if let Some(retries) = run_matches.value_of("retries") {
+        let retries = retries.parse::<u32>().map_err(|e| format!("Invalid number of retries '{}'. {}", retries, e))?;
+        client_config.connection.retries = Some(retries);
+    }
+
+    if let Some(proxy_command) = run_matches.value_of("proxy-command") {
+        client_config.connection.proxy_command = Some(proxy_command.to_string());
+    }
+
+    if let Some(pair_matches) = run_matches.subcommand_matches(SUBCOMMAND_PAIR) {
+        return pair(pair_matches, client_config);
+    }
+
+    if let Some(import_matches) = run_matches.subcommand_matches(SUBCOMMAND_IMPORT) {
+        let imported_config = rclip_client_lib::import_bundle(
+            import_matches.value_of("bundle").unwrap_or_default(),
+            import_matches.is_present("force"),
+        )?;
+
+        if let (Some(host), Some(port)) = (imported_config.server.host, imported_config.server.port) {
+            println!("Configured server '{}'.", rclip_config::format_address(&host, port));
+        }
+
+        return Ok(());
+    }
+
     if client_config.certificate.der_cert_pub.is_none() {
         client_config.certificate.der_cert_pub =
             rclip_config::resolve_default_cert_path(rclip_config::DEFAULT_FILENAME_DER_CERT_PUB);
@@ -69,22 +282,70 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     }
 
     if let Some(proposed_port) = run_matches.value_of("port") {
-        client_config.server.port = Some(proposed_port.parse::<u16>()?)
+        client_config.server.port = Some(proposed_port.parse::<u32>()?)
     }
 
     if let Some(key_pub_loc) = run_matches.value_of("der-cert-pub") {
         client_config.certificate.der_cert_pub = Some(key_pub_loc.to_string());
     };
 
-    if client_config.certificate.der_cert_pub.is_none() {
-        return Err("Please provide the public certificate argument for --der-cert-pub.".into());
+    if let Some(trust_mode) = run_matches.value_of("trust-mode") {
+        client_config.certificate.trust_mode = Some(trust_mode.to_string());
     }
 
-    if let Some(key_loc) = client_config.certificate.der_cert_pub.clone() {
-        let key_path = Path::new(&key_loc);
+    if run_matches.is_present("accept-new") && run_matches.value_of("trust-mode").is_none() {
+        client_config.certificate.trust_mode = Some(rclip_config::TRUST_MODE_TOFU.to_string());
+    }
+
+    if let Some(ca_file) = run_matches.value_of("ca-file") {
+        client_config.certificate.ca_file = Some(ca_file.to_string());
+    }
+
+    if let Some(server_name) = run_matches.value_of("server-name") {
+        client_config.certificate.server_name = Some(server_name.to_string());
+    }
+
+    if let Some(client_cert) = run_matches.value_of("client-cert") {
+        client_config.certificate.client_cert = Some(client_cert.to_string());
+    }
+
+    if let Some(client_key) = run_matches.value_of("client-key") {
+        client_config.certificate.client_key = Some(client_key.to_string());
+    }
+
+    if let Some(token) = run_matches.value_of("token") {
+        client_config.auth.token = Some(token.to_string());
+    }
+
+    if let Some(fingerprints) = run_matches.values_of("fingerprint") {
+        client_config.certificate.fingerprints = Some(fingerprints.map(|f| f.to_string()).collect());
+    }
+
+    // The public certificate is only needed when pinned without any fingerprint
+    let fingerprints_provided = client_config
+        .certificate
+        .fingerprints
+        .as_ref()
+        .map(|fingerprints| !fingerprints.is_empty())
+        .unwrap_or(false);
+
+    let trust_mode = client_config
+        .certificate
+        .trust_mode
+        .as_deref()
+        .unwrap_or(rclip_config::TRUST_MODE_PINNED);
 
-        if !key_path.exists() {
-            return Err(format!("The public key file doesn't exists at '{}'!", &key_loc).into());
+    if trust_mode == rclip_config::TRUST_MODE_PINNED && !fingerprints_provided {
+        if client_config.certificate.der_cert_pub.is_none() {
+            return Err("Please provide the public certificate argument for --der-cert-pub, or a --fingerprint.".into());
+        }
+
+        if let Some(key_loc) = client_config.certificate.der_cert_pub.clone() {
+            let key_path = Path::new(&key_loc);
+
+            if !key_path.exists() {
+                return Err(format!("The public key file doesn't exists at '{}'!", &key_loc).into());
+            }
         }
     }
 
@@ -92,37 +353,162 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     let proposed_cmd = run_matches.value_of("command").unwrap_or("READ");
 
-    let clipboard_cmd = match proposed_cmd {
-        "READ" => common::ClipboardCmd {
-            name: "READ".to_string(),
-            text: None,
-        },
-        "CLEAR" => common::ClipboardCmd {
-            name: "CLEAR".to_string(),
-            text: Some(String::new()),
-        },
-        _ => common::ClipboardCmd {
-            name: "WRITE".to_string(),
-            text: match cmd_text_opt {
-                Some(x) => Some(x.to_string()),
-                _ => {
-                    if let Ok(clipboard_contents) = common::get_clipboard_contents() {
-                        Some(clipboard_contents)
-                    } else {
-                        return Err("Could not acquire clipboard contents.".into());
-                    }
-                }
+    if run_matches.is_present("trust-next") && proposed_cmd != "STATUS" {
+        return Err("The --trust-next flag requires the STATUS command.".into());
+    }
+
+    let command = match proposed_cmd {
+        "READ" => Command::Read,
+        "CLEAR" => Command::Clear,
+        "STATUS" => Command::Status,
+        _ => match cmd_text_opt {
+            Some(text) => Command::Write(text),
+            None => match common::get_clipboard_contents() {
+                Ok(clipboard_contents) => Command::Write(clipboard_contents),
+                Err(e) => return Err(ClientError::Clipboard(format!("Could not acquire clipboard contents. {}", e)).into()),
             },
         },
     };
 
-    if let (Some(server_host), Some(server_port), Some(der_cert_pub)) = (
-        client_config.server.host,
-        client_config.server.port,
-        client_config.certificate.der_cert_pub,
-    ) {
-        common::send_cmd(server_host, server_port, der_cert_pub, clipboard_cmd)
+    let new_server_prompt: rclip_client_lib::NewServerPrompt = if run_matches.is_present("accept-new") {
+        accept_new_server
     } else {
-        Err("Client error! Some required parameters are were not provided: missing public certificate?".into())
+        confirm_new_server
+    };
+
+    let client = RclipClient::connect(client_config.clone())?
+        .with_new_server_prompt(new_server_prompt)
+        .with_verbose(true);
+
+    let response = common::send_cmd(&client, command)?;
+
+    if proposed_cmd == "STATUS" {
+        println!("{}", response);
+
+        if run_matches.is_present("trust-next") {
+            trust_next_certificate(&client_config.certificate, &response)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Pins the next certificate advertised by the server ahead of its rotation.
+fn trust_next_certificate(
+    certificate: &rclip_config::ClientCertificate,
+    status: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let trust_mode = certificate.trust_mode.as_deref().unwrap_or(rclip_config::TRUST_MODE_PINNED);
+
+    if trust_mode != rclip_config::TRUST_MODE_PINNED {
+        return Err(format!("Only pinned certificates need to be trusted ahead of time, the trust mode is '{}'.", trust_mode).into());
+    }
+
+    let next_fingerprint = match rclip_client_lib::status_value(status, "next-fingerprint") {
+        Some(next_fingerprint) => rclip_config::certs::normalize_fingerprint(next_fingerprint)?,
+        None => {
+            println!("The server hasn't prepared its next certificate yet.");
+            return Ok(());
+        }
+    };
+
+    // Only the configuration file is updated, without the command-line arguments
+    let mut client_config: rclip_config::ClientConfig =
+        rclip_config::load_default_config(DEFAULT_CONFIG_FILENAME_CLIENT).map_err(|e| e.to_string())?;
+    let fingerprints = client_config.certificate.fingerprints.get_or_insert_with(Vec::new);
+
+    if fingerprints.contains(&next_fingerprint) {
+        println!("The next certificate is already trusted.");
+        return Ok(());
+    }
+
+    fingerprints.push(next_fingerprint.clone());
+    rclip_config::save_config(client_config, DEFAULT_CONFIG_FILENAME_CLIENT).map_err(|e| e.to_string())?;
+
+    println!("Added the next certificate fingerprint {} to the configuration.", next_fingerprint);
+
+    Ok(())
+}
+
+fn accept_new_server(_address: &str, _fingerprint: &str) -> bool {
+    true
+}
+
+fn confirm_new_server(address: &str, fingerprint: &str) -> bool {
+    eprintln!("The authenticity of server '{}' can't be established.", address);
+    eprintln!("The certificate fingerprint is {}.", fingerprint);
+    eprint!("Are you sure you want to trust this server (yes/no)? ");
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+
+    match io::stdin().read_line(&mut answer) {
+        Ok(_) => ["yes", "y"].contains(&answer.trim().to_lowercase().as_str()),
+        Err(_) => false,
     }
 }
+
+// Installs the certificate of the server, and the token it may issue, into the data and configuration directories.
+// The connection settings come with the command-line arguments, but only the configuration file is updated.
+fn pair(matches: &ArgMatches, client_config: rclip_config::ClientConfig) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Checked before using the one-time code, which couldn't be saved otherwise
+    let mut saved_config: rclip_config::ClientConfig = rclip_config::load_default_config(DEFAULT_CONFIG_FILENAME_CLIENT)
+        .map_err(|e| format!("The configuration file can't be updated, please fix it first. {}", e))?;
+
+    let server_host = matches
+        .value_of("host")
+        .map(|host| host.to_string())
+        .or(client_config.server.host)
+        .ok_or("Please provide the server host argument for --host.")?;
+
+    let server_port = match matches.value_of("port") {
+        Some(port) => port.parse::<u32>()?,
+        None => client_config.server.port.unwrap_or(rclip_config::DEFAULT_SERVER_PORT),
+    };
+
+    let client_name = matches
+        .value_of("name")
+        .map(|name| name.to_string())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .unwrap_or_else(|| DEFAULT_CLIENT_NAME.to_string());
+
+    let code = matches.value_of("code").unwrap_or_default();
+    let paired_server = rclip_client_lib::pair(
+        &server_host,
+        server_port,
+        code,
+        &client_name,
+        &client_config.tls,
+        &client_config.connection,
+        true,
+    )?;
+
+    let cert_path = rclip_client_lib::install_server_certificate(&paired_server.certificate, matches.is_present("force"))?;
+
+    saved_config.server.host = Some(server_host.clone());
+    saved_config.server.port = Some(server_port);
+    saved_config.certificate.der_cert_pub = Some(cert_path.clone());
+    saved_config.certificate.trust_mode = Some(rclip_config::TRUST_MODE_PINNED.to_string());
+    saved_config.certificate.fingerprints = None;
+    saved_config.certificate.server_name = None;
+
+    if let Some(token) = paired_server.token.clone() {
+        saved_config.auth.token = Some(token);
+    }
+
+    rclip_config::save_config(saved_config, DEFAULT_CONFIG_FILENAME_CLIENT).map_err(|e| e.to_string())?;
+
+    println!(
+        "Paired with server '{}' with fingerprint {}.",
+        rclip_config::format_address(&server_host, server_port),
+        paired_server.fingerprint
+    );
+    println!("Server certificate: {}", cert_path);
+
+    if paired_server.token.is_some() {
+        println!("A client token was issued and saved to the configuration.");
+    }
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
 #![windows_subsystem = "windows"]
 
 use fltk::{app, button, dialog, draw, enums, frame, input, prelude::*, window};
-use rclip_config;
+use rclip_client_lib::{Command, RclipClient};
 use std::cell::RefCell;
 use std::error::Error;
 use std::rc::Rc;
@@ -38,7 +38,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
     let host_input_rc = Rc::new(RefCell::new(input::Input::default().with_size(200, 20)));
 
-    let client_config = if let Ok(cfg) = rclip_config::load_default_config(common::DEFAULT_CONFIG_FILENAME_CLIENT) {
+    let client_config = if let Ok(cfg) = rclip_config::load_default_config(rclip_client_lib::DEFAULT_CONFIG_FILENAME_CLIENT) {
         cfg
     } else {
         rclip_config::ClientConfig::default()
@@ -117,23 +117,44 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .with_size(BUTTON_WIDTH, 20)
         .with_label("Clear");
     button_clear.set_tooltip("Clear clipboard server text");
+    let mut button_import = button::Button::default()
+        .with_size(BUTTON_WIDTH, 20)
+        .with_label("Import...");
+    button_import.set_tooltip("Import a connection bundle (rclip:// URI or file)");
+
+    // Settings other than the server address and the public key path only come from the configuration file
+    let base_config = Rc::new(RefCell::new(client_config.clone()));
+
+    fn with_cert_path(base: &rclip_config::ClientConfig, cert_path: String) -> rclip_config::ClientConfig {
+        let mut client_config = base.clone();
+        client_config.certificate.der_cert_pub = if cert_path.is_empty() { None } else { Some(cert_path) };
+        client_config
+    }
+
+    fn confirm_new_server(address: &str, fingerprint: &str) -> bool {
+        let message = format!(
+            "The authenticity of server '{}' can't be established.\nThe certificate fingerprint is {}.\n\nAre you sure you want to trust this server?",
+            address, fingerprint
+        );
+
+        dialog::choice2_default(&message, "No", "Yes", "") == Some(1)
+    }
 
     fn send_cmd(
         host_text: String,
         port_text: String,
-        key_pub_der: String,
-        cmd_name: &str,
-        cmd_text: Option<String>,
+        mut client_config: rclip_config::ClientConfig,
+        command: Command,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let clipboard_cmd = common::ClipboardCmd {
-            name: cmd_name.to_string(),
-            text: cmd_text,
-        };
+        client_config.server.host = Some(host_text);
+        client_config.server.port = Some(port_text.parse::<u32>()?);
 
-        let server_port = port_text.parse::<u16>()?;
+        let client = RclipClient::connect(client_config)?
+            .with_new_server_prompt(confirm_new_server)
+            .with_verbose(true);
 
-        if let Err(ex) = common::send_cmd(host_text, server_port, key_pub_der, clipboard_cmd) {
-            Err(format!("{}", ex.to_string()).into())
+        if let Err(ex) = common::send_cmd(&client, command) {
+            Err(ex.to_string().into())
         } else {
             Ok(())
         }
@@ -143,6 +164,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         let input_host_ref = host_input_rc.clone();
         let input_port_ref = port_input_rc.clone();
         let input_pub_cert_ref = key_input_rc.clone();
+        let base_config = base_config.clone();
         let wind_ref = wind.clone();
 
         move |_| {
@@ -151,9 +173,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             let cert_path = input_pub_cert_ref.borrow().value();
 
             if let Ok(clipboard_contents) = common::get_clipboard_contents() {
-                let cmd_text_opt = Some(clipboard_contents);
-
-                if let Err(ex) = send_cmd(host_text, port_text, cert_path, "WRITE", cmd_text_opt) {
+                if let Err(ex) = send_cmd(host_text, port_text, with_cert_path(&base_config.borrow(), cert_path), Command::Write(clipboard_contents)) {
                     dialog::alert(
                         wind_ref.x(),
                         wind_ref.y() + wind_ref.height() / 2,
@@ -175,11 +195,12 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         let input_host_ref = host_input_rc.clone();
         let input_port_ref = port_input_rc.clone();
         let input_pub_cert_ref = key_input_rc.clone();
+        let base_config = base_config.clone();
 
         move |_| {
             let host_text = input_host_ref.borrow().value();
             let port_text = input_port_ref.borrow().value();
-            let ret_port_number = port_text.parse::<u16>();
+            let ret_port_number = port_text.parse::<u32>();
             let cert_path = input_pub_cert_ref.borrow().value();
 
             let inputs_to_check = [&host_text, &port_text, &cert_path];
@@ -190,7 +211,16 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             ];
             let mut err_found = false;
 
-            for i in 0..inputs_to_check.len() {
+            // The public key is only needed when pinned without any fingerprint
+            let inputs_count = if base_config.borrow().certificate.trust_mode.as_deref().unwrap_or(rclip_config::TRUST_MODE_PINNED) != rclip_config::TRUST_MODE_PINNED
+                || base_config.borrow().certificate.fingerprints.as_ref().map(|f| !f.is_empty()).unwrap_or(false)
+            {
+                inputs_to_check.len() - 1
+            } else {
+                inputs_to_check.len()
+            };
+
+            for i in 0..inputs_count {
                 if inputs_to_check[i].is_empty() {
                     dialog::alert(
                         wind_ref.x(),
@@ -208,23 +238,19 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
             match ret_port_number {
                 Ok(port_number) => {
-                    let mut client_config = rclip_config::ClientConfig::default();
+                    let mut client_config = with_cert_path(&base_config.borrow(), cert_path.to_owned());
 
                     client_config.server = rclip_config::Server {
                         host: Some(host_text.to_owned()),
                         port: Some(port_number),
                     };
 
-                    client_config.certificate = rclip_config::ClientCertificate {
-                        der_cert_pub: Some(cert_path.to_owned()),
-                    };
-
                     if let Err(ex) = rclip_config::save_config(
                         client_config,
-                        common::DEFAULT_CONFIG_FILENAME_CLIENT,
+                        rclip_client_lib::DEFAULT_CONFIG_FILENAME_CLIENT,
                     ) {
                         let err_msg =
-                            format!("ERROR: Failed to save settings!\n{}", ex.to_string());
+                            format!("ERROR: Failed to save settings!\n{}", ex);
                         dialog::alert(wind_ref.x(), wind_ref.y() + wind_ref.height() / 2, &err_msg);
                     } else {
                         dialog::alert(
@@ -236,7 +262,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                 }
                 Err(ex) => {
                     let err_msg =
-                        format!("ERROR: Failed to parse port number!\n{}", ex.to_string());
+                        format!("ERROR: Failed to parse port number!\n{}", ex);
                     dialog::alert(wind_ref.x(), wind_ref.y() + wind_ref.height() / 2, &err_msg);
                 }
             }
@@ -247,15 +273,15 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         let input_host_ref = host_input_rc.clone();
         let input_port_ref = port_input_rc.clone();
         let input_pub_cert_ref = key_input_rc.clone();
+        let base_config = base_config.clone();
         let wind_ref = wind.clone();
 
         move |_| {
             let port_text = input_port_ref.borrow().value();
             let host_text = input_host_ref.borrow().value();
-            let cmd_text_opt = Some(String::new());
             let cert_path = input_pub_cert_ref.borrow().value();
 
-            if let Err(ex) = send_cmd(host_text, port_text, cert_path, "CLEAR", cmd_text_opt) {
+            if let Err(ex) = send_cmd(host_text, port_text, with_cert_path(&base_config.borrow(), cert_path), Command::Clear) {
                 dialog::alert(
                     wind_ref.x(),
                     wind_ref.y() + wind_ref.height() / 2,
@@ -268,6 +294,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     button_receive.set_callback({
         let input_pub_cert_ref = key_input_rc.clone();
         let input_port_ref = port_input_rc.clone();
+        let base_config = base_config.clone();
         let wind_ref = wind.clone();
         let input_host_ref = host_input_rc.clone();
 
@@ -277,7 +304,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             let cert_path = input_pub_cert_ref.borrow().value();
             let wind_ref = wind_ref.clone();
 
-            if let Err(ex) = send_cmd(host_text, port_text, cert_path, "READ", None) {
+            if let Err(ex) = send_cmd(host_text, port_text, with_cert_path(&base_config.borrow(), cert_path), Command::Read) {
                 dialog::alert(
                     wind_ref.x(),
                     wind_ref.y() + wind_ref.height() / 2,
@@ -287,6 +314,49 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         }
     });
 
+    button_import.set_callback({
+        let wind_ref = wind.clone();
+        let input_host_ref = host_input_rc.clone();
+        let input_port_ref = port_input_rc.clone();
+        let input_pub_cert_ref = key_input_rc.clone();
+        let base_config = base_config.clone();
+
+        move |_| {
+            let source = match dialog::input_default("Connection bundle (rclip:// URI or file path):", "") {
+                Some(source) if !source.trim().is_empty() => source,
+                _ => return,
+            };
+
+            match rclip_client_lib::import_bundle(&source, false) {
+                Ok(imported_config) => {
+                    if let Some(server_host) = &imported_config.server.host {
+                        input_host_ref.borrow_mut().set_value(server_host);
+                    }
+
+                    if let Some(server_port) = imported_config.server.port {
+                        input_port_ref.borrow_mut().set_value(&server_port.to_string());
+                    }
+
+                    input_pub_cert_ref
+                        .borrow_mut()
+                        .set_value(imported_config.certificate.der_cert_pub.as_deref().unwrap_or_default());
+
+                    *base_config.borrow_mut() = imported_config;
+
+                    dialog::alert(
+                        wind_ref.x(),
+                        wind_ref.y() + wind_ref.height() / 2,
+                        "Successfully imported connection bundle!",
+                    );
+                }
+                Err(ex) => {
+                    let err_msg = format!("ERROR: Failed to import connection bundle!\n{}", ex);
+                    dialog::alert(wind_ref.x(), wind_ref.y() + wind_ref.height() / 2, &err_msg);
+                }
+            }
+        }
+    });
+
     wind.handle({
         let mut host_frame = host_frame.clone();
         let host_input_rc = host_input_rc.clone();
@@ -303,6 +373,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         let mut button_receive = button_receive.clone();
         let mut button_send = button_send.clone();
         let mut button_clear = button_clear.clone();
+        let mut button_import = button_import.clone();
 
         let lw = {
             let mut lw = 100;
@@ -378,6 +449,7 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                     BUTTON_WIDTH,
                     ROW_HEIGHT,
                 );
+                button_import.resize(SIZE_PACK_SPACING, widy, BUTTON_WIDTH, ROW_HEIGHT);
 
                 true
             }
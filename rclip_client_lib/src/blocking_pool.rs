@@ -0,0 +1,61 @@
+// Client for async code, running the blocking exchanges of an RclipClient on the tokio blocking
+// thread pool. Each exchange in progress holds a thread of the pool (512 at most by default) for
+// as long as it lasts, timeouts included, rather than a task of the runtime.
+
+use crate::{ClientError, Command, NewServerPrompt, RclipClient};
+
+#[derive(Clone)]
+pub struct BlockingPoolClient {
+    client: RclipClient,
+}
+
+impl BlockingPoolClient {
+    pub fn connect(config: rclip_config::ClientConfig) -> Result<Self, ClientError> {
+        Ok(Self {
+            client: RclipClient::connect(config)?,
+        })
+    }
+
+    // The prompt runs on a blocking thread.
+    pub fn with_new_server_prompt(self, new_server_prompt: NewServerPrompt) -> Self {
+        Self {
+            client: self.client.with_new_server_prompt(new_server_prompt),
+        }
+    }
+
+    pub fn with_verbose(self, verbose: bool) -> Self {
+        Self {
+            client: self.client.with_verbose(verbose),
+        }
+    }
+
+    pub fn address(&self) -> String {
+        self.client.address()
+    }
+
+    pub async fn read(&self) -> Result<String, ClientError> {
+        self.send(Command::Read).await
+    }
+
+    pub async fn write(&self, text: &str) -> Result<(), ClientError> {
+        self.send(Command::Write(text.to_string())).await.map(|_| ())
+    }
+
+    pub async fn clear(&self) -> Result<(), ClientError> {
+        self.send(Command::Clear).await.map(|_| ())
+    }
+
+    pub async fn status(&self) -> Result<String, ClientError> {
+        self.send(Command::Status).await
+    }
+
+    pub async fn send(&self, command: Command) -> Result<String, ClientError> {
+        let client = self.client.clone();
+
+        match tokio::task::spawn_blocking(move || client.send(&command)).await {
+            Ok(ret) => ret,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(ClientError::Other(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// Failures of the client, by class, so that callers can tell them apart without parsing messages.
+
+use rclip_protocol::{ErrorCode, ErrorResponse};
+use std::error::Error;
+use std::fmt;
+
+use crate::exchange::{ExchangeError, Stage};
+
+#[derive(Debug)]
+pub enum ClientError {
+    // The server couldn't be reached, or the connection failed midway
+    Connection(ExchangeError),
+    // The server certificate isn't trusted, or the server rejected the client certificate
+    Trust(String),
+    // Missing or unknown client token, failed pairing
+    Auth(String),
+    // Request larger than accepted by the server
+    Limit(String),
+    // Request rejected by the server, or unexpected response
+    Protocol(String),
+    // Failure of the server itself
+    Server(String),
+    // Local clipboard failure, reported by the clients
+    Clipboard(String),
+    // Anything else, i.e. invalid settings or unreadable certificate files
+    Other(String),
+}
+
+impl ClientError {
+    pub fn message(&self) -> String {
+        match self {
+            ClientError::Connection(e) => e.to_string(),
+            ClientError::Trust(message)
+            | ClientError::Auth(message)
+            | ClientError::Limit(message)
+            | ClientError::Protocol(message)
+            | ClientError::Server(message)
+            | ClientError::Clipboard(message)
+            | ClientError::Other(message) => message.clone(),
+        }
+    }
+}
+
+// Handshakes fail because of the certificates, unless the connection itself fails
+impl From<ExchangeError> for ClientError {
+    fn from(e: ExchangeError) -> Self {
+        if e.stage == Stage::Handshake && !e.is_transient() {
+            ClientError::Trust(e.to_string())
+        } else {
+            ClientError::Connection(e)
+        }
+    }
+}
+
+impl From<ErrorResponse> for ClientError {
+    fn from(e: ErrorResponse) -> Self {
+        match e.code {
+            ErrorCode::Auth => ClientError::Auth(e.message),
+            ErrorCode::Limit => ClientError::Limit(e.message),
+            ErrorCode::Protocol => ClientError::Protocol(e.message),
+            ErrorCode::Internal => ClientError::Server(e.message),
+        }
+    }
+}
+
+impl From<rclip_protocol::DecodeError> for ClientError {
+    fn from(e: rclip_protocol::DecodeError) -> Self {
+        ClientError::Protocol(e.to_string())
+    }
+}
+
+impl From<String> for ClientError {
+    fn from(message: String) -> Self {
+        ClientError::Other(message)
+    }
+}
+
+impl From<&str> for ClientError {
+    fn from(message: &str) -> Self {
+        ClientError::Other(message.to_string())
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for ClientError {
+    fn from(e: Box<dyn Error + Send + Sync>) -> Self {
+        match e.downcast::<ExchangeError>() {
+            Ok(e) => ClientError::from(*e),
+            Err(e) => ClientError::Other(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Connection(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,584 @@
+// Connections to the server over TCP, proxies, vsock, Unix sockets or devices, and the TLS exchange of a request.
+
+use crate::ClientError;
+use rclip_config::proxy::Proxy;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+trait Transport: Read + Write {}
+
+impl<T: Read + Write> Transport for T {}
+
+// Defaults of the [connection] configuration section, timeouts in seconds and delays in milliseconds
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const DEFAULT_READ_TIMEOUT: u64 = 30;
+const DEFAULT_WRITE_TIMEOUT: u64 = 30;
+const DEFAULT_RETRY_DELAY: u64 = 500;
+const MAX_RETRY_DELAY: u64 = 30_000;
+
+#[derive(Clone, Copy)]
+struct Timeouts {
+    connect: Option<Duration>,
+    read: Option<Duration>,
+    write: Option<Duration>,
+}
+
+impl Timeouts {
+    fn new(connection: &rclip_config::ClientConnection) -> Self {
+        // 0 disables a timeout
+        let timeout = |secs: Option<u64>, default_secs: u64| match secs.unwrap_or(default_secs) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
+
+        Self {
+            connect: timeout(connection.connect_timeout, DEFAULT_CONNECT_TIMEOUT),
+            read: timeout(connection.read_timeout, DEFAULT_READ_TIMEOUT),
+            write: timeout(connection.write_timeout, DEFAULT_WRITE_TIMEOUT),
+        }
+    }
+}
+
+// Steps of an exchange with the server, reported by errors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Connect,
+    Handshake,
+    Send,
+    Receive,
+}
+
+pub struct ExchangeError {
+    pub stage: Stage,
+    address: String,
+    timeout: Option<Duration>,
+    error: Box<dyn Error + Send + Sync>,
+}
+
+impl ExchangeError {
+    fn new<E: Into<Box<dyn Error + Send + Sync>>>(stage: Stage, address: &str, timeout: Option<Duration>, error: E) -> Self {
+        Self {
+            stage,
+            address: address.to_string(),
+            timeout,
+            error: error.into(),
+        }
+    }
+
+    fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        self.error.downcast_ref::<io::Error>().map(|e| e.kind())
+    }
+
+    // Blocking reads and writes report expired timeouts as WouldBlock under Unix
+    pub fn is_timeout(&self) -> bool {
+        matches!(self.io_error_kind(), Some(io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock))
+    }
+
+    // Failures which may go away by trying again, i.e. while the server restarts.
+    pub fn is_transient(&self) -> bool {
+        use io::ErrorKind::*;
+
+        matches!(
+            self.io_error_kind(),
+            Some(
+                ConnectionRefused
+                    | ConnectionReset
+                    | ConnectionAborted
+                    | NotConnected
+                    | AddrNotAvailable
+                    | BrokenPipe
+                    | TimedOut
+                    | WouldBlock
+                    | UnexpectedEof
+                    | Interrupted
+            )
+        )
+    }
+}
+
+impl std::fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self.stage {
+            Stage::Connect => "Could not connect to",
+            Stage::Handshake => "TLS handshake failed with",
+            Stage::Send => "Could not send the request to",
+            Stage::Receive => "Could not receive the response from",
+        };
+
+        match self.timeout {
+            Some(timeout) if self.is_timeout() => {
+                write!(f, "{} '{}': timed out after {}s", action, self.address, timeout.as_secs())
+            }
+            _ => write!(f, "{} '{}': {}", action, self.address, self.error),
+        }
+    }
+}
+
+// Errors returned from main are printed with Debug
+impl std::fmt::Debug for ExchangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl Error for ExchangeError {}
+
+// Where and how to reach the server.
+struct Endpoint<'a> {
+    host: &'a str,
+    port: u32,
+    address: String,
+    timeouts: Timeouts,
+    proxy: Option<Proxy>,
+    proxy_command: Option<String>,
+}
+
+fn connect(endpoint: &Endpoint) -> Result<Box<dyn Transport>, Box<dyn Error + Send + Sync>> {
+    let (server_host, port_number, timeouts) = (endpoint.host, endpoint.port, endpoint.timeouts);
+
+    if let Some(cid) = rclip_config::parse_vsock_cid(server_host).map_err(|e| e.to_string())? {
+        return connect_vsock(cid, port_number, timeouts);
+    }
+
+    if let Some(path) = server_host.strip_prefix(rclip_config::UNIX_PREFIX) {
+        return connect_unix(path, timeouts);
+    }
+
+    // Devices block until the other end opens them, timeouts don't apply
+    if let Some(path) = server_host.strip_prefix(rclip_config::DEVICE_PREFIX) {
+        return connect_device(path);
+    }
+
+    let port_number = rclip_config::network_port(server_host, port_number)?;
+
+    // Only TCP connections go through the proxy command or proxy, only the read timeout applies to commands
+    if let Some(command) = &endpoint.proxy_command {
+        let transport = crate::proxy_command::CommandTransport::spawn(command, server_host, port_number, timeouts.read)?;
+        return Ok(Box::new(transport));
+    }
+
+    match &endpoint.proxy {
+        Some(proxy) => {
+            let mut stream = connect_tcp(&proxy.host, proxy.port, timeouts)
+                .map_err(|e| io::Error::new(e.kind(), format!("Could not connect to the proxy '{}'. {}", proxy.address(), e)))?;
+
+            // Tunneling is part of connecting
+            stream.set_read_timeout(timeouts.connect)?;
+            stream.set_write_timeout(timeouts.connect)?;
+            crate::proxy::tunnel(&mut stream, proxy, server_host, port_number)?;
+            stream.set_read_timeout(timeouts.read)?;
+            stream.set_write_timeout(timeouts.write)?;
+
+            Ok(Box::new(stream))
+        }
+        None => Ok(Box::new(connect_tcp(server_host, port_number, timeouts)?)),
+    }
+}
+
+fn connect_tcp(server_host: &str, port_number: u16, timeouts: Timeouts) -> io::Result<net::TcpStream> {
+    use std::net::ToSocketAddrs;
+
+    let mut last_error = None;
+
+    // Each resolved address is tried in turn, i.e. IPv6 then IPv4 for localhost
+    for addr in (rclip_config::unbracket_host(server_host), port_number).to_socket_addrs()? {
+        let stream = match timeouts.connect {
+            Some(timeout) => net::TcpStream::connect_timeout(&addr, timeout),
+            None => net::TcpStream::connect(addr),
+        };
+
+        match stream {
+            Ok(stream) => {
+                stream.set_read_timeout(timeouts.read)?;
+                stream.set_write_timeout(timeouts.write)?;
+
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("No address found for '{}'.", server_host))
+    }))
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str, timeouts: Timeouts) -> Result<Box<dyn Transport>, Box<dyn Error + Send + Sync>> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_read_timeout(timeouts.read)?;
+    stream.set_write_timeout(timeouts.write)?;
+
+    Ok(Box::new(FramedTransport::open(stream)?))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &str, _timeouts: Timeouts) -> Result<Box<dyn Transport>, Box<dyn Error + Send + Sync>> {
+    Err("Unix socket addresses are only supported under Unix systems.".into())
+}
+
+#[cfg(unix)]
+fn connect_device(path: &str) -> Result<Box<dyn Transport>, Box<dyn Error + Send + Sync>> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    if !Path::new(path).exists() {
+        return Err(format!("Cannot find device at: {}", path).into());
+    }
+
+    let device = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)?;
+
+    make_raw(&device)?;
+
+    Ok(Box::new(FramedTransport::open(device)?))
+}
+
+#[cfg(not(unix))]
+fn connect_device(_path: &str) -> Result<Box<dyn Transport>, Box<dyn Error + Send + Sync>> {
+    Err("Device addresses are only supported under Unix systems.".into())
+}
+
+// Disables the line discipline of terminals, so that ptys and serial lines carry bytes unchanged.
+#[cfg(unix)]
+fn make_raw(device: &fs::File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = device.as_raw_fd();
+
+    unsafe {
+        if libc::isatty(fd) != 1 {
+            return Ok(());
+        }
+
+        let mut termios = std::mem::zeroed::<libc::termios>();
+
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        libc::cfmakeraw(&mut termios);
+
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// Frames used on persistent channels (character devices), which never report the end of a session:
+// a type byte, a big-endian u32 payload length and the payload. Must match the server framing.
+const FRAME_START: u8 = b'S';
+const FRAME_DATA: u8  = b'D';
+const FRAME_END: u8   = b'E';
+const FRAME_HEADER_LEN: usize = 5;
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+struct FramedTransport<S: Read + Write> {
+    inner: S,
+    remaining: usize,
+    ended: bool,
+}
+
+impl<S: Read + Write> FramedTransport<S> {
+    // Opens a session and waits for its acknowledgement, skipping stale data from previous sessions.
+    fn open(inner: S) -> io::Result<Self> {
+        let mut ret = Self {
+            inner,
+            remaining: 0,
+            ended: false,
+        };
+
+        ret.write_frame(FRAME_START, &[])?;
+
+        let start_frame = [FRAME_START, 0, 0, 0, 0];
+        let mut window = [0xffu8; FRAME_HEADER_LEN];
+        let mut byte = [0u8; 1];
+
+        while window != start_frame {
+            ret.inner.read_exact(&mut byte)?;
+            window.rotate_left(1);
+            window[FRAME_HEADER_LEN - 1] = byte[0];
+        }
+
+        Ok(ret)
+    }
+
+    fn write_frame(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.push(kind);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.inner.write_all(&frame)?;
+        self.inner.flush()
+    }
+}
+
+impl<S: Read + Write> Read for FramedTransport<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            if self.ended {
+                return Ok(0);
+            }
+
+            let mut header = [0u8; FRAME_HEADER_LEN];
+            self.inner.read_exact(&mut header)?;
+            let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+
+            match header[0] {
+                FRAME_DATA if len <= MAX_FRAME_LEN => self.remaining = len,
+                FRAME_END => self.ended = true,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Unexpected frame received from the server",
+                    ))
+                }
+            }
+        }
+
+        let max = std::cmp::min(buf.len(), self.remaining);
+        let n = self.inner.read(&mut buf[..max])?;
+
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.remaining -= n;
+
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for FramedTransport<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = std::cmp::min(buf.len(), MAX_FRAME_LEN);
+        self.write_frame(FRAME_DATA, &buf[..len])?;
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Read + Write> Drop for FramedTransport<S> {
+    fn drop(&mut self) {
+        let _ = self.write_frame(FRAME_END, &[]);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn connect_vsock(cid: u32, port: u32, timeouts: Timeouts) -> Result<Box<dyn Transport>, Box<dyn Error + Send + Sync>> {
+    use socket2::{Domain, SockAddr, Socket, Type};
+
+    let socket = Socket::new(Domain::VSOCK, Type::STREAM, None)?;
+    let addr = SockAddr::vsock(cid, port);
+
+    match timeouts.connect {
+        Some(timeout) => socket.connect_timeout(&addr, timeout)?,
+        None => socket.connect(&addr)?,
+    }
+
+    socket.set_read_timeout(timeouts.read)?;
+    socket.set_write_timeout(timeouts.write)?;
+
+    Ok(Box::new(socket))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_vsock(_cid: u32, _port: u32, _timeouts: Timeouts) -> Result<Box<dyn Transport>, Box<dyn Error + Send + Sync>> {
+    Err("vsock addresses are only supported under Linux.".into())
+}
+
+// Sends a request over TLS and returns the whole response, trying again after transient failures.
+// When verbose, the address is printed and retries are reported on the standard error.
+pub(crate) fn exchange(
+    server_host: &str,
+    port_number: u32,
+    config: rustls::ClientConfig,
+    server_name: rustls::ServerName,
+    request: &str,
+    connection: &rclip_config::ClientConnection,
+    verbose: bool,
+) -> Result<String, ClientError> {
+    let endpoint = Endpoint {
+        host: server_host,
+        port: port_number,
+        address: rclip_config::format_address(server_host, port_number),
+        timeouts: Timeouts::new(connection),
+        proxy: Proxy::configured(connection.proxy.as_deref())?,
+        proxy_command: connection.proxy_command.clone().filter(|command| !command.trim().is_empty()),
+    };
+
+    match (&endpoint.proxy_command, &endpoint.proxy) {
+        _ if !verbose => {}
+        (Some(command), _) => println!("Connecting with server at address:'{}' through command '{}'.", endpoint.address, command),
+        (None, Some(proxy)) => println!("Connecting with server at address:'{}' through proxy '{}'.", endpoint.address, proxy.address()),
+        (None, None) => println!("Connecting with server at address:'{}'.", endpoint.address),
+    }
+
+    let config = Arc::new(config);
+    let retries = connection.retries.unwrap_or(0);
+    let mut retry_delay = connection.retry_delay.unwrap_or(DEFAULT_RETRY_DELAY);
+    let mut attempt = 0;
+
+    loop {
+        match exchange_once(&endpoint, config.clone(), server_name.clone(), request) {
+            Ok(response) => return Ok(response),
+            Err(e) if attempt < retries && e.is_transient() => {
+                attempt += 1;
+
+                if verbose {
+                    eprintln!("{}. Retrying in {} ms ({}/{}).", e.to_string().trim_end_matches('.'), retry_delay, attempt, retries);
+                }
+
+                thread::sleep(Duration::from_millis(retry_delay));
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn exchange_once(
+    endpoint: &Endpoint,
+    config: Arc<rustls::ClientConfig>,
+    server_name: rustls::ServerName,
+    request: &str,
+) -> Result<String, ExchangeError> {
+    let (addr, timeouts) = (endpoint.address.as_str(), endpoint.timeouts);
+
+    let mut socket = connect(endpoint)
+        .map_err(|e| ExchangeError::new(Stage::Connect, addr, timeouts.connect, e))?;
+    let mut connection = rustls::ClientConnection::new(config, server_name)
+        .map_err(|e| ExchangeError::new(Stage::Handshake, addr, None, e))?;
+
+    // The handshake is completed upfront, so that its failures aren't reported as sending ones
+    while connection.is_handshaking() {
+        connection
+            .complete_io(&mut socket)
+            .map_err(|e| ExchangeError::new(Stage::Handshake, addr, timeouts.read, e))?;
+    }
+
+    let mut tls = rustls::Stream::new(&mut connection, &mut socket);
+
+    tls.write_all(rclip_protocol::frame_request(request).as_bytes())
+        .and_then(|_| tls.flush())
+        .map_err(|e| ExchangeError::new(Stage::Send, addr, timeouts.write, e))?;
+
+    let mut response = String::new();
+    tls.read_to_string(&mut response)
+        .map_err(|e| ExchangeError::new(Stage::Receive, addr, timeouts.read, e))?;
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Server side of a persistent channel: what the server sends, and what it received from the client
+    struct Channel<'a> {
+        input: &'a [u8],
+        output: &'a mut Vec<u8>,
+    }
+
+    impl Read for Channel<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Channel<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let mut ret = vec![kind];
+        ret.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        ret.extend_from_slice(payload);
+        ret
+    }
+
+    fn frames(mut bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut ret = Vec::new();
+
+        while !bytes.is_empty() {
+            let len = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) as usize;
+            ret.push((bytes[0], bytes[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len].to_vec()));
+            bytes = &bytes[FRAME_HEADER_LEN + len..];
+        }
+
+        ret
+    }
+
+    #[test]
+    fn round_trips_sessions() {
+        let response = vec![0x17; MAX_FRAME_LEN + 10];
+        let mut input = b"stale".to_vec();
+        input.extend(frame(FRAME_START, &[]));
+        input.extend(frame(FRAME_DATA, &response[..MAX_FRAME_LEN]));
+        input.extend(frame(FRAME_DATA, &response[MAX_FRAME_LEN..]));
+        input.extend(frame(FRAME_END, &[]));
+        let mut output = Vec::new();
+
+        {
+            let mut transport = FramedTransport::open(Channel { input: &input, output: &mut output }).unwrap();
+            transport.write_all(&vec![0x16; MAX_FRAME_LEN + 1]).unwrap();
+
+            let mut received = Vec::new();
+            transport.read_to_end(&mut received).unwrap();
+            assert_eq!(received, response);
+            assert_eq!(transport.read(&mut [0; 16]).unwrap(), 0);
+        }
+
+        let sent = frames(&output);
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[0], (FRAME_START, Vec::new()));
+        assert_eq!(sent[1], (FRAME_DATA, vec![0x16; MAX_FRAME_LEN]));
+        assert_eq!(sent[2], (FRAME_DATA, vec![0x16]));
+        assert_eq!(sent[3], (FRAME_END, Vec::new()));
+    }
+
+    #[test]
+    fn rejects_truncated_and_oversized_frames() {
+        let mut output = Vec::new();
+        let mut truncated = frame(FRAME_START, &[]);
+        truncated.extend(frame(FRAME_DATA, b"record"));
+        truncated.pop();
+
+        let mut transport = FramedTransport::open(Channel { input: &truncated, output: &mut output }).unwrap();
+        let err = transport.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        drop(transport);
+
+        let mut oversized = frame(FRAME_START, &[]);
+        oversized.extend(frame(FRAME_DATA, &vec![0; MAX_FRAME_LEN + 1]));
+
+        let mut transport = FramedTransport::open(Channel { input: &oversized, output: &mut output }).unwrap();
+        let err = transport.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        drop(transport);
+
+        // Without the acknowledgement of the server
+        let err = FramedTransport::open(Channel { input: b"stale", output: &mut output }).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,95 @@
+// Fingerprints of the servers trusted on first use, one "<address> <fingerprint>" line
+// per server in the rclip data directory, similar to the known_hosts file of SSH.
+
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const FILENAME_KNOWN_SERVERS: &str = "known_servers";
+
+pub struct KnownServer {
+    pub fingerprint: String,
+    pub line_number: usize,
+}
+
+pub struct KnownServers {
+    pub path: PathBuf,
+}
+
+impl KnownServers {
+    pub fn open_default() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let data_dir = rclip_config::create_data_dir().map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            path: data_dir.join(FILENAME_KNOWN_SERVERS),
+        })
+    }
+
+    pub fn find(&self, address: &str) -> Result<Option<KnownServer>, String> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Could not read '{}'. {}", self.path.display(), e))?;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // The fingerprint comes last, as paths of unix: and device: addresses may contain spaces
+            if let Some((known_address, fingerprint)) = line.rsplit_once(char::is_whitespace) {
+                if known_address.trim_end() == address {
+                    return Ok(Some(KnownServer {
+                        fingerprint: fingerprint.to_string(),
+                        line_number: i + 1,
+                    }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn add(&self, address: &str, fingerprint: &str) -> Result<(), String> {
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Could not open '{}'. {}", self.path.display(), e))?;
+
+        writeln!(f, "{} {}", address, fingerprint)
+            .map_err(|e| format!("Could not write '{}'. {}", self.path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_added_servers() {
+        let path = std::env::temp_dir().join(format!("rclip-known-servers-{}", std::process::id()));
+        fs::write(&path, "# comment\n\nunix:/run/my rclip.sock  sha256:aaaa\n").unwrap();
+        let known_servers = KnownServers { path };
+
+        known_servers.add("127.0.0.1:10080", "sha256:bbbb").unwrap();
+        known_servers.add("[::1]:10080", "sha256:cccc").unwrap();
+
+        let found = known_servers.find("unix:/run/my rclip.sock").unwrap().unwrap();
+        assert_eq!((found.fingerprint.as_str(), found.line_number), ("sha256:aaaa", 3));
+
+        let found = known_servers.find("[::1]:10080").unwrap().unwrap();
+        assert_eq!((found.fingerprint.as_str(), found.line_number), ("sha256:cccc", 5));
+
+        assert!(known_servers.find("127.0.0.1:10081").unwrap().is_none());
+        assert!(known_servers.find("127.0.0.1").unwrap().is_none());
+
+        let _ = fs::remove_file(&known_servers.path);
+        assert!(known_servers.find("127.0.0.1:10080").unwrap().is_none());
+    }
+}
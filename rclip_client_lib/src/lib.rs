@@ -0,0 +1,134 @@
+// Client of rclip-server, shared by the command-line and graphical clients.
+//
+// Each command opens its own connection, as the server closes it after responding:
+//
+//     let client = rclip_client_lib::RclipClient::connect(client_config)?;
+//     client.write("some text")?;
+//     let text = client.read()?;
+
+use rclip_protocol::{Request, Response};
+
+mod error;
+mod exchange;
+mod known_servers;
+mod pairing;
+mod proxy;
+mod proxy_command;
+mod session_store;
+mod verify;
+
+#[cfg(feature = "async")]
+mod blocking_pool;
+
+pub use error::ClientError;
+pub use exchange::{ExchangeError, Stage};
+pub use pairing::{import_bundle, install_server_certificate, pair, PairedServer};
+pub use rclip_protocol::Command;
+pub use verify::NewServerPrompt;
+
+#[cfg(feature = "async")]
+pub use blocking_pool::BlockingPoolClient;
+
+pub const DEFAULT_CONFIG_FILENAME_CLIENT: &str = "config-client.toml";
+
+// Unknown servers are rejected unless a prompt accepts them, in the 'tofu' trust mode
+fn reject_new_server(_address: &str, _fingerprint: &str) -> bool {
+    false
+}
+
+#[derive(Clone)]
+pub struct RclipClient {
+    server_host: String,
+    port_number: u32,
+    config: rclip_config::ClientConfig,
+    new_server_prompt: NewServerPrompt,
+    verbose: bool,
+}
+
+impl RclipClient {
+    // Checks the server address and connection settings of a configuration, nothing is sent yet.
+    pub fn connect(config: rclip_config::ClientConfig) -> Result<Self, ClientError> {
+        let (server_host, port_number) = match (config.server.host.clone(), config.server.port) {
+            (Some(server_host), Some(port_number)) => (server_host, port_number),
+            _ => return Err("Client error! Some required parameters are were not provided: missing host or port?".into()),
+        };
+
+        rclip_config::proxy::Proxy::configured(config.connection.proxy.as_deref())?;
+
+        Ok(Self {
+            server_host,
+            port_number,
+            config,
+            new_server_prompt: reject_new_server,
+            verbose: false,
+        })
+    }
+
+    // Asks whether to trust servers seen for the first time, in the 'tofu' trust mode.
+    pub fn with_new_server_prompt(mut self, new_server_prompt: NewServerPrompt) -> Self {
+        self.new_server_prompt = new_server_prompt;
+        self
+    }
+
+    // Prints the address of the server and reports retries, as the clients do.
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn address(&self) -> String {
+        rclip_config::format_address(&self.server_host, self.port_number)
+    }
+
+    // Returns the server clipboard text.
+    pub fn read(&self) -> Result<String, ClientError> {
+        self.send(&Command::Read)
+    }
+
+    pub fn write(&self, text: &str) -> Result<(), ClientError> {
+        self.send(&Command::Write(text.to_string())).map(|_| ())
+    }
+
+    pub fn clear(&self) -> Result<(), ClientError> {
+        self.send(&Command::Clear).map(|_| ())
+    }
+
+    // Returns the "key=value" lines describing the server, see status_value.
+    pub fn status(&self) -> Result<String, ClientError> {
+        self.send(&Command::Status)
+    }
+
+    // Returns the body of a successful response, the message of an error response being the error.
+    pub fn send(&self, command: &Command) -> Result<String, ClientError> {
+        let (tls_config, server_name) = verify::tls_config(
+            &self.server_host,
+            self.port_number,
+            &self.config.certificate,
+            &self.config.tls,
+            self.new_server_prompt,
+        )?;
+
+        let request = Request::new(command.clone(), self.config.auth.token.clone()).to_string();
+
+        let response = exchange::exchange(
+            &self.server_host,
+            self.port_number,
+            tls_config,
+            server_name,
+            &request,
+            &self.config.connection,
+            self.verbose,
+        )?;
+
+        Ok(Response::decode(&response)?.into_result()?)
+    }
+}
+
+// Returns the value of a "key=value" line of a STATUS response.
+pub fn status_value<'a>(status: &'a str, key: &str) -> Option<&'a str> {
+    status
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
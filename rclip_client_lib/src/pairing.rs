@@ -0,0 +1,144 @@
+// Setup of the client for a server: pairing with a one-time code, or importing a connection bundle.
+
+use rclip_protocol::{Command, Request, Response};
+use std::error::Error;
+use std::fs;
+
+use crate::{ClientError, DEFAULT_CONFIG_FILENAME_CLIENT};
+
+// What a server hands over when pairing.
+pub struct PairedServer {
+    pub certificate: Vec<u8>,
+    pub fingerprint: String,
+    pub token: Option<String>,
+}
+
+pub fn pair(
+    server_host: &str,
+    port_number: u32,
+    code: &str,
+    client_name: &str,
+    tls_policy: &rclip_config::tls::TlsPolicy,
+    connection: &rclip_config::ClientConnection,
+    verbose: bool,
+) -> Result<PairedServer, ClientError> {
+    let code = rclip_config::pairing::PairingCode::parse(code)?;
+    let command = Command::Pair {
+        secret: code.secret.clone(),
+        client_name: client_name.trim().to_string(),
+    };
+    let request = Request::new(command, None).to_string();
+    let (config, server_name) = crate::verify::pairing_tls_config(code.clone(), tls_policy)?;
+
+    // Pairing codes are single-use, a request that reached the server cannot be sent again
+    let connection = rclip_config::ClientConnection {
+        retries: Some(0),
+        ..connection.clone()
+    };
+
+    let response = crate::exchange::exchange(server_host, port_number, config, server_name, &request, &connection, verbose)?;
+
+    let response = Response::decode(&response)?.into_result()?;
+
+    let mut certificate = Vec::new();
+    let mut token = None;
+
+    for line in response.lines() {
+        match line.split_once('=') {
+            Some(("certificate", hex)) => certificate = decode_hex(hex)?,
+            Some(("token", value)) if !value.is_empty() => token = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    let fingerprint = rclip_config::certs::fingerprint(&certificate);
+
+    // The certificate must be the one that was just verified
+    if certificate.is_empty() || !code.matches_fingerprint(&fingerprint) {
+        return Err(ClientError::Trust(
+            "The certificate received from the server doesn't match the pairing code.".to_string(),
+        ));
+    }
+
+    Ok(PairedServer {
+        certificate,
+        fingerprint,
+        token,
+    })
+}
+
+// usize::is_multiple_of only exists since Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn decode_hex(hex: &str) -> Result<Vec<u8>, ClientError> {
+    // Slicing by byte offsets is only safe on ASCII
+    if !hex.is_ascii() || hex.len() % 2 != 0 {
+        return Err(ClientError::Protocol(
+            "Invalid certificate received from the server, expecting pairs of hexadecimal digits.".to_string(),
+        ));
+    }
+
+    (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|e| ClientError::Protocol(format!("Invalid certificate received from the server. {}", e)))
+}
+
+// Saves the certificate of a paired or imported server to the data directory, returning its path.
+// The certificate of another server is only replaced when forced.
+pub fn install_server_certificate(cert_der: &[u8], force: bool) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let data_dir = rclip_config::create_data_dir().map_err(|e| e.to_string())?;
+    let cert_path = data_dir.join(rclip_config::DEFAULT_FILENAME_DER_CERT_PUB);
+
+    if cert_path.exists() && !force && fs::read(&cert_path)? != cert_der {
+        return Err(format!(
+            "The file '{}' already exists for another server, remove it or use --force to replace it.",
+            cert_path.display()
+        )
+        .into());
+    }
+
+    fs::write(&cert_path, cert_der)?;
+
+    Ok(cert_path.display().to_string())
+}
+
+// Writes the client configuration for the server of a connection bundle (rclip:// URI or file).
+pub fn import_bundle(source: &str, force: bool) -> Result<rclip_config::ClientConfig, Box<dyn Error + Send + Sync>> {
+    let bundle = rclip_config::bundle::ClientBundle::load(source)?;
+
+    let mut client_config: rclip_config::ClientConfig =
+        rclip_config::load_default_config(DEFAULT_CONFIG_FILENAME_CLIENT).map_err(|e| e.to_string())?;
+
+    client_config.certificate.der_cert_pub = match bundle.certificate_der()? {
+        Some(cert_der) => Some(install_server_certificate(&cert_der, force)?),
+        None => None,
+    };
+
+    client_config.server = bundle.server;
+    client_config.certificate.fingerprints = Some(bundle.fingerprints);
+    client_config.certificate.trust_mode = Some(rclip_config::TRUST_MODE_PINNED.to_string());
+    client_config.certificate.server_name = None;
+    client_config.auth.token = bundle.token;
+
+    rclip_config::save_config(client_config.clone(), DEFAULT_CONFIG_FILENAME_CLIENT).map_err(|e| e.to_string())?;
+
+    Ok(client_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_hex_certificates() {
+        assert_eq!(decode_hex("00ff3A").unwrap(), vec![0x00, 0xff, 0x3a]);
+        assert!(decode_hex("").unwrap().is_empty());
+
+        for hex in ["abc", "0g", "é0", "0é", "a\u{20ac}b"] {
+            match decode_hex(hex) {
+                Err(ClientError::Protocol(_)) => {}
+                _ => panic!("Expected a protocol error for '{}'.", hex),
+            }
+        }
+    }
+}
@@ -0,0 +1,409 @@
+// Tunnels through SOCKS5 (RFC 1928 and RFC 1929) and HTTP CONNECT proxies. The server host name
+// is resolved by the proxy, as clients behind proxies often can't resolve external names.
+
+use rclip_config::proxy::{Proxy, ProxyKind};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream};
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_AUTH_NONE: u8 = 0;
+const SOCKS_AUTH_PASSWORD: u8 = 2;
+const SOCKS_AUTH_UNACCEPTABLE: u8 = 0xff;
+const SOCKS_PASSWORD_VERSION: u8 = 1;
+const SOCKS_CMD_CONNECT: u8 = 1;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+
+// Upper bound of the HTTP response headers
+const MAX_HTTP_HEADERS_LEN: usize = 16 * 1024;
+
+// Asks the proxy at the other end of the stream to connect to the server.
+pub fn tunnel(stream: &mut TcpStream, proxy: &Proxy, server_host: &str, port_number: u16) -> io::Result<()> {
+    let server_host = rclip_config::unbracket_host(server_host);
+
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5_connect(stream, proxy, server_host, port_number),
+        ProxyKind::HttpConnect => http_connect(stream, proxy, server_host, port_number),
+    }
+}
+
+fn socks5_connect(stream: &mut TcpStream, proxy: &Proxy, server_host: &str, port_number: u16) -> io::Result<()> {
+    let methods: &[u8] = if proxy.username.is_some() {
+        &[SOCKS_AUTH_NONE, SOCKS_AUTH_PASSWORD]
+    } else {
+        &[SOCKS_AUTH_NONE]
+    };
+
+    let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting)?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error(proxy, "replied with an unexpected SOCKS version"));
+    }
+
+    match reply[1] {
+        SOCKS_AUTH_NONE => {}
+        SOCKS_AUTH_PASSWORD if proxy.username.is_some() => socks5_authenticate(stream, proxy)?,
+        SOCKS_AUTH_PASSWORD | SOCKS_AUTH_UNACCEPTABLE if proxy.username.is_none() => {
+            return Err(proxy_error(proxy, "requires a username and password"));
+        }
+        _ => return Err(proxy_error(proxy, "doesn't support any of the offered authentication methods")),
+    }
+
+    let mut request = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+
+    match server_host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(SOCKS_ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(SOCKS_ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let name = server_host.as_bytes();
+
+            if name.len() > u8::MAX as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The host name '{}' is too long for a SOCKS5 proxy.", server_host),
+                ));
+            }
+
+            request.push(SOCKS_ATYP_DOMAIN);
+            request.push(name.len() as u8);
+            request.extend_from_slice(name);
+        }
+    }
+
+    request.extend_from_slice(&port_number.to_be_bytes());
+    stream.write_all(&request)?;
+
+    // Version, status, reserved byte and type of the bound address
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error(proxy, "replied with an unexpected SOCKS version"));
+    }
+
+    if reply[1] != 0 {
+        let (kind, reason) = match reply[1] {
+            2 => (io::ErrorKind::PermissionDenied, "connection not allowed by ruleset"),
+            3 => (io::ErrorKind::ConnectionRefused, "network unreachable"),
+            4 => (io::ErrorKind::ConnectionRefused, "host unreachable"),
+            5 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+            6 => (io::ErrorKind::TimedOut, "TTL expired"),
+            7 => (io::ErrorKind::Unsupported, "command not supported"),
+            8 => (io::ErrorKind::Unsupported, "address type not supported"),
+            _ => (io::ErrorKind::Other, "general failure"),
+        };
+
+        return Err(io::Error::new(
+            kind,
+            format!("The SOCKS5 proxy '{}' could not connect to the server: {}.", proxy.address(), reason),
+        ));
+    }
+
+    // The bound address and port are of no use, but must be consumed
+    let bound_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(proxy_error(proxy, "sent an invalid reply")),
+    };
+
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    Ok(())
+}
+
+fn socks5_authenticate(stream: &mut TcpStream, proxy: &Proxy) -> io::Result<()> {
+    let username = proxy.username.as_deref().unwrap_or_default().as_bytes();
+    let password = proxy.password.as_deref().unwrap_or_default().as_bytes();
+
+    if username.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+        return Err(proxy_error(proxy, "doesn't accept usernames or passwords longer than 255 bytes"));
+    }
+
+    let mut request = vec![SOCKS_PASSWORD_VERSION, username.len() as u8];
+    request.extend_from_slice(username);
+    request.push(password.len() as u8);
+    request.extend_from_slice(password);
+    stream.write_all(&request)?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("The SOCKS5 proxy '{}' rejected the username or password.", proxy.address()),
+        ));
+    }
+
+    Ok(())
+}
+
+fn http_connect(stream: &mut TcpStream, proxy: &Proxy, server_host: &str, port_number: u16) -> io::Result<()> {
+    // IPv6 literals are bracketed in the request target
+    let target = rclip_config::format_address(server_host, port_number.into());
+    let mut request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+
+    if let Some(authorization) = proxy.basic_authorization() {
+        request.push_str(&format!("Proxy-Authorization: {}\r\n", authorization));
+    }
+
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    // Read byte by byte, so that nothing past the headers is consumed
+    let mut headers = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while !headers.ends_with(b"\r\n\r\n") {
+        if headers.len() >= MAX_HTTP_HEADERS_LEN {
+            return Err(proxy_error(proxy, "sent a too long response"));
+        }
+
+        stream.read_exact(&mut byte)?;
+        headers.push(byte[0]);
+    }
+
+    let headers = String::from_utf8_lossy(&headers);
+    let status_line = headers.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+
+    if !status_line.starts_with("HTTP/") || status.len() != 3 {
+        return Err(proxy_error(proxy, "didn't reply with an HTTP response"));
+    }
+
+    if !status.starts_with('2') {
+        let kind = match status {
+            "407" => io::ErrorKind::PermissionDenied,
+            // Bad gateway and gateway timeout, usually temporary
+            "502" | "504" => io::ErrorKind::ConnectionRefused,
+            _ => io::ErrorKind::Other,
+        };
+
+        return Err(io::Error::new(
+            kind,
+            format!("The HTTP proxy '{}' refused the connection: {}", proxy.address(), status_line),
+        ));
+    }
+
+    Ok(())
+}
+
+fn proxy_error(proxy: &Proxy, message: &str) -> io::Error {
+    let kind = match proxy.kind {
+        ProxyKind::Socks5 => "SOCKS5",
+        ProxyKind::HttpConnect => "HTTP",
+    };
+
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("The {} proxy '{}' {}.", kind, proxy.address(), message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    const SOCKS_REPLY_SUCCESS: [u8; 10] = [SOCKS_VERSION, 0, 0, SOCKS_ATYP_IPV4, 127, 0, 0, 1, 0x27, 0x60];
+
+    // Accepts a single connection on a loopback port, scripted by the handler.
+    fn fake_proxy(scheme: &str, credentials: &str, handler: impl FnOnce(TcpStream) + Send + 'static) -> (Proxy, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("{}://{}127.0.0.1:{}", scheme, credentials, listener.local_addr().unwrap().port());
+        let proxy = Proxy::parse(&url).unwrap();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handler(stream);
+        });
+
+        (proxy, handle)
+    }
+
+    fn connect(proxy: &Proxy) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(proxy.address())?;
+        tunnel(&mut stream, proxy, "example.org", 10080)?;
+
+        Ok(stream)
+    }
+
+    fn read_bytes(stream: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut ret = vec![0u8; len];
+        stream.read_exact(&mut ret).unwrap();
+
+        ret
+    }
+
+    // Reads the SOCKS5 connect request, expecting example.org:10080
+    fn read_socks5_request(stream: &mut TcpStream) {
+        let mut expected = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0, SOCKS_ATYP_DOMAIN, 11];
+        expected.extend_from_slice(b"example.org");
+        expected.extend_from_slice(&10080u16.to_be_bytes());
+
+        assert_eq!(read_bytes(stream, expected.len()), expected);
+    }
+
+    fn read_http_request(stream: &mut TcpStream) -> Vec<String> {
+        let mut reader = io::BufReader::new(stream);
+        let mut ret = Vec::new();
+
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+
+            if line == "\r\n" {
+                return ret;
+            }
+
+            ret.push(line.trim_end().to_string());
+        }
+    }
+
+    #[test]
+    fn tunnels_through_socks5_proxies() {
+        let (proxy, handle) = fake_proxy("socks5", "", |mut stream| {
+            assert_eq!(read_bytes(&mut stream, 3), [SOCKS_VERSION, 1, SOCKS_AUTH_NONE]);
+            stream.write_all(&[SOCKS_VERSION, SOCKS_AUTH_NONE]).unwrap();
+            read_socks5_request(&mut stream);
+            stream.write_all(&SOCKS_REPLY_SUCCESS).unwrap();
+            stream.write_all(b"tunneled").unwrap();
+        });
+
+        let mut stream = connect(&proxy).unwrap();
+        let mut data = String::new();
+        stream.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "tunneled");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn authenticates_to_socks5_proxies() {
+        for (password, status) in [("secret", 0), ("wrong", 1)] {
+            let (proxy, handle) = fake_proxy("socks5", &format!("me:{}@", password), move |mut stream| {
+                assert_eq!(read_bytes(&mut stream, 4), [SOCKS_VERSION, 2, SOCKS_AUTH_NONE, SOCKS_AUTH_PASSWORD]);
+                stream.write_all(&[SOCKS_VERSION, SOCKS_AUTH_PASSWORD]).unwrap();
+
+                let mut expected = vec![SOCKS_PASSWORD_VERSION, 2];
+                expected.extend_from_slice(b"me");
+                expected.push(password.len() as u8);
+                expected.extend_from_slice(password.as_bytes());
+                assert_eq!(read_bytes(&mut stream, expected.len()), expected);
+                stream.write_all(&[SOCKS_PASSWORD_VERSION, status]).unwrap();
+
+                if status == 0 {
+                    read_socks5_request(&mut stream);
+                    stream.write_all(&SOCKS_REPLY_SUCCESS).unwrap();
+                }
+            });
+
+            let ret = connect(&proxy);
+            handle.join().unwrap();
+
+            match status {
+                0 => assert!(ret.is_ok()),
+                _ => assert_eq!(ret.unwrap_err().kind(), io::ErrorKind::PermissionDenied),
+            }
+        }
+
+        // Without credentials to offer
+        let (proxy, handle) = fake_proxy("socks5", "", |mut stream| {
+            read_bytes(&mut stream, 3);
+            stream.write_all(&[SOCKS_VERSION, SOCKS_AUTH_UNACCEPTABLE]).unwrap();
+        });
+
+        let err = connect(&proxy).unwrap_err();
+        assert!(err.to_string().contains("requires a username and password"), "{}", err);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn reports_socks5_refusals() {
+        let (proxy, handle) = fake_proxy("socks5", "", |mut stream| {
+            read_bytes(&mut stream, 3);
+            stream.write_all(&[SOCKS_VERSION, SOCKS_AUTH_NONE]).unwrap();
+            read_socks5_request(&mut stream);
+            stream.write_all(&[SOCKS_VERSION, 5, 0, SOCKS_ATYP_IPV4, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        let err = connect(&proxy).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains("connection refused"), "{}", err);
+        handle.join().unwrap();
+
+        // A SOCKS4 reply to the connect request
+        let (proxy, handle) = fake_proxy("socks5", "", |mut stream| {
+            read_bytes(&mut stream, 3);
+            stream.write_all(&[SOCKS_VERSION, SOCKS_AUTH_NONE]).unwrap();
+            read_socks5_request(&mut stream);
+            stream.write_all(&[0, 0x5a, 0, 0, 0, 0, 0, 0]).unwrap();
+        });
+
+        let err = connect(&proxy).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("unexpected SOCKS version"), "{}", err);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn tunnels_through_http_proxies() {
+        let (proxy, handle) = fake_proxy("http", "me:secret@", |mut stream| {
+            let request = read_http_request(&mut stream);
+            assert_eq!(request[0], "CONNECT example.org:10080 HTTP/1.1");
+            assert!(request.contains(&"Host: example.org:10080".to_string()), "{:?}", request);
+            assert!(request.contains(&"Proxy-Authorization: Basic bWU6c2VjcmV0".to_string()), "{:?}", request);
+
+            stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunneled").unwrap();
+        });
+
+        // Data following the response headers belongs to the tunnel
+        let mut stream = connect(&proxy).unwrap();
+        let mut data = String::new();
+        stream.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "tunneled");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn reports_http_refusals() {
+        let responses = [
+            ("HTTP/1.1 407 Proxy Authentication Required", io::ErrorKind::PermissionDenied),
+            ("HTTP/1.1 502 Bad Gateway", io::ErrorKind::ConnectionRefused),
+            ("HTTP/1.1 403 Forbidden", io::ErrorKind::Other),
+            ("SSH-2.0-OpenSSH_9.6", io::ErrorKind::InvalidData),
+        ];
+
+        for (status_line, kind) in responses {
+            let (proxy, handle) = fake_proxy("http", "", move |mut stream| {
+                let request = read_http_request(&mut stream);
+                assert!(!request.iter().any(|line| line.starts_with("Proxy-Authorization:")), "{:?}", request);
+
+                stream.write_all(format!("{}\r\n\r\n", status_line).as_bytes()).unwrap();
+            });
+
+            let err = connect(&proxy).unwrap_err();
+            assert_eq!(err.kind(), kind, "{}", err);
+            handle.join().unwrap();
+        }
+    }
+}
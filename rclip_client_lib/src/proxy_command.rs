@@ -0,0 +1,301 @@
+// Transport over the standard input and output of a command, similar to the ProxyCommand of SSH,
+// i.e. "ssh jump nc %h %p". The command runs in the shell, with %h replaced by the server host,
+// %p by the server port and %% by a percent sign. Hosts may come from imported connection bundles,
+// so that only hostname and IP address characters are accepted, none being special to the shell. Its standard error is left to the terminal,
+// so that password prompts still show up.
+//
+// Pipes have no timeouts, so that the output of the command is read by a helper thread and a read
+// waiting longer than the read timeout kills the command.
+
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// How long to wait for the exit status, once the command closed its output
+const EXIT_STATUS_WAIT: Duration = Duration::from_millis(100);
+const OUTPUT_CHUNK_LEN: usize = 16 * 1024;
+
+pub struct CommandTransport {
+    command: String,
+    child: Child,
+    stdin: ChildStdin,
+    // Chunks of output from the helper thread, an empty one at the end of the output
+    output: mpsc::Receiver<io::Result<Vec<u8>>>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+    ended: bool,
+    read_timeout: Option<Duration>,
+}
+
+impl CommandTransport {
+    pub fn spawn(command: &str, server_host: &str, port_number: u16, read_timeout: Option<Duration>) -> io::Result<Self> {
+        let server_host = rclip_config::unbracket_host(server_host);
+
+        if !is_safe_host(server_host) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The server host '{}' can't be passed to a proxy command.", server_host),
+            ));
+        }
+
+        let command = expand(command, server_host, port_number);
+
+        let mut child = shell(&command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("Could not run the proxy command '{}'. {}", command, e)))?;
+
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            // io::Error::other only exists since Rust 1.74
+            #[allow(clippy::io_other_error)]
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Could not open the proxy command pipes.")),
+        };
+
+        let (sender, output) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut stdout = stdout;
+
+            loop {
+                let mut chunk = vec![0u8; OUTPUT_CHUNK_LEN];
+
+                match stdout.read(&mut chunk) {
+                    Ok(n) => {
+                        chunk.truncate(n);
+
+                        if sender.send(Ok(chunk)).is_err() || n == 0 {
+                            return;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            command,
+            child,
+            stdin,
+            output,
+            pending: Vec::new(),
+            pending_pos: 0,
+            ended: false,
+            read_timeout,
+        })
+    }
+
+    fn next_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let received = match self.read_timeout {
+            Some(timeout) => self.output.recv_timeout(timeout),
+            None => self.output.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(chunk) => chunk,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Ok(Vec::new()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // Closes the output, which also ends the helper thread
+                let _ = self.child.kill();
+
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("The proxy command '{}' didn't answer in time.", self.command),
+                ))
+            }
+        }
+    }
+
+    // A failed command reports its exit status rather than an unexpected end of file.
+    fn exit_error(&mut self) -> Option<io::Error> {
+        let mut waited = Duration::ZERO;
+
+        while waited < EXIT_STATUS_WAIT {
+            match self.child.try_wait() {
+                Ok(Some(status)) if status.success() => return None,
+                Ok(Some(status)) => {
+                    return Some(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        format!("The proxy command '{}' failed ({}).", self.command, status),
+                    ));
+                }
+                Ok(None) => {
+                    thread::sleep(Duration::from_millis(10));
+                    waited += Duration::from_millis(10);
+                }
+                Err(_) => return None,
+            }
+        }
+
+        None
+    }
+}
+
+impl Read for CommandTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.ended {
+            return Ok(0);
+        }
+
+        if self.pending_pos == self.pending.len() {
+            self.pending = self.next_chunk()?;
+            self.pending_pos = 0;
+
+            if self.pending.is_empty() {
+                self.ended = true;
+
+                return match self.exit_error() {
+                    Some(e) => Err(e),
+                    None => Ok(0),
+                };
+            }
+        }
+
+        let n = std::cmp::min(buf.len(), self.pending.len() - self.pending_pos);
+        buf[..n].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + n]);
+        self.pending_pos += n;
+
+        Ok(n)
+    }
+}
+
+impl Write for CommandTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stdin.write(buf) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Err(self.exit_error().unwrap_or_else(|| {
+                io::Error::new(e.kind(), format!("The proxy command '{}' exited.", self.command))
+            })),
+            ret => ret,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl Drop for CommandTransport {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Letters, digits and the separators of host names and IP addresses, i.e. "fe80::1%eth0".
+fn is_safe_host(host: &str) -> bool {
+    !host.is_empty()
+        && !host.starts_with('-')
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '%'))
+}
+
+fn expand(command: &str, server_host: &str, port_number: u16) -> String {
+    let mut ret = String::with_capacity(command.len());
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            ret.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('h') => ret.push_str(server_host),
+            Some('p') => ret.push_str(&port_number.to_string()),
+            Some('%') => ret.push('%'),
+            Some(other) => {
+                ret.push('%');
+                ret.push(other);
+            }
+            None => ret.push('%'),
+        }
+    }
+
+    ret
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut ret = Command::new("sh");
+    ret.arg("-c").arg(command);
+    ret
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut ret = Command::new("cmd");
+    ret.arg("/C").arg(command);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_placeholders() {
+        assert_eq!(expand("ssh jump nc %h %p", "rclip.internal", 10080), "ssh jump nc rclip.internal 10080");
+        assert_eq!(expand("echo 100%% %x %", "::1", 1), "echo 100% %x %");
+        assert_eq!(expand("%h%p%h", "a", 2), "a2a");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn talks_to_the_command() {
+        let mut transport = CommandTransport::spawn("cat", "localhost", 10080, Some(Duration::from_secs(10))).unwrap();
+        transport.write_all(b"client hello").unwrap();
+        transport.flush().unwrap();
+
+        let mut response = [0u8; 12];
+        transport.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"client hello");
+
+        let mut transport = CommandTransport::spawn("printf '%%s' '%h:%p'", "[::1]", 10080, None).unwrap();
+        let mut response = String::new();
+        transport.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "::1:10080");
+    }
+
+    #[test]
+    fn rejects_hostile_hosts() {
+        for host in ["$(touch${IFS}pwned)", "`id`", "a;id", "a b", "a|id", "'a'", "-oProxyCommand=id", ""] {
+            assert!(!is_safe_host(host), "{}", host);
+
+            let err = CommandTransport::spawn("echo %h", host, 10080, None).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        for host in ["rclip.internal", "192.168.122.1", "[fe80::1%eth0]", "vsock_host-2"] {
+            assert!(is_safe_host(rclip_config::unbracket_host(host)), "{}", host);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_failed_commands() {
+        let mut transport = CommandTransport::spawn("sh -c 'exit 3'", "localhost", 10080, None).unwrap();
+        let err = transport.read(&mut [0u8; 16]).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn times_out_silent_commands() {
+        let started = std::time::Instant::now();
+        let mut transport = CommandTransport::spawn("sleep 30", "localhost", 10080, Some(Duration::from_millis(100))).unwrap();
+        let err = transport.read(&mut [0u8; 16]).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
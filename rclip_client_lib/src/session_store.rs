@@ -0,0 +1,111 @@
+// TLS sessions kept between runs of the client, so that repeated invocations resume the previous session
+// instead of going through a full handshake. One "<key> <value> <scope>" line per session, hex encoded,
+// in the rclip data directory. Sessions hold resumption secrets, so the file is only readable by its owner.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const FILENAME_SESSION_TICKETS: &str = "session_tickets";
+
+// Older sessions are dropped first
+const MAX_SESSIONS: usize = 64;
+
+pub struct PersistentSessionStore {
+    path: PathBuf,
+    // Sessions are only offered to the server they come from, as pinned servers share the same TLS server name,
+    // and with unchanged trust settings, as resumed sessions skip the verification of the server certificate
+    scope: String,
+    lock: Mutex<()>,
+}
+
+impl PersistentSessionStore {
+    pub fn open_default(scope: &str) -> Result<Self, String> {
+        let data_dir = rclip_config::create_data_dir().map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            path: data_dir.join(FILENAME_SESSION_TICKETS),
+            scope: scope.to_string(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn read_entries(&self) -> Vec<(String, String, String)> {
+        fs::read_to_string(&self.path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, ' ');
+
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(key), Some(value), Some(scope)) => {
+                        Some((key.to_string(), value.to_string(), scope.to_string()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    // Replaces the file at once, so that concurrent invocations never read a partial file.
+    fn write_entries(&self, entries: &[(String, String, String)]) -> std::io::Result<()> {
+        let tmp_path = self.path.with_extension(format!("tmp{}", std::process::id()));
+        let mut options = fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut f = options.open(&tmp_path)?;
+
+        for (key, value, scope) in entries {
+            writeln!(f, "{} {} {}", key, value, scope)?;
+        }
+
+        drop(f);
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+impl rustls::client::StoresClientSessions for PersistentSessionStore {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        let _lock = match self.lock.lock() {
+            Ok(lock) => lock,
+            Err(_) => return false,
+        };
+
+        let key = to_hex(&key);
+        let mut entries = self.read_entries();
+        entries.retain(|(k, _, scope)| !(*k == key && *scope == self.scope));
+        entries.push((key, to_hex(&value), self.scope.clone()));
+
+        if entries.len() > MAX_SESSIONS {
+            entries.drain(..entries.len() - MAX_SESSIONS);
+        }
+
+        self.write_entries(&entries).is_ok()
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let key = to_hex(key);
+
+        self.read_entries()
+            .into_iter()
+            .find(|(k, _, scope)| *k == key && *scope == self.scope)
+            .and_then(|(_, value, _)| from_hex(&value))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,492 @@
+// Server certificate verification, either against a pinned certificate (default), against
+// the certificates trusted on first use, or with the standard validation of the chain and
+// names against a CA bundle.
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ServerName};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::known_servers::KnownServers;
+use crate::session_store::PersistentSessionStore;
+use rclip_config::pairing::PairingCode;
+
+// Same algorithms as the rustls verifier
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+// Pins the server certificate on its SHA-256 fingerprint, either configured or computed from a certificate file.
+struct AcceptSpecificCertsVerifier {
+    fingerprints: Vec<String>,
+}
+
+impl ServerCertVerifier for AcceptSpecificCertsVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = rclip_config::certs::fingerprint(&end_entity.0);
+
+        if self.fingerprints.contains(&fingerprint) {
+            return Ok(rustls::client::ServerCertVerified::assertion());
+        }
+
+        Err(rustls::Error::General(format!(
+            "Unknown certificate issuer, the server certificate fingerprint is {}.",
+            fingerprint
+        )))
+    }
+}
+
+// Asked to trust the certificate fingerprint of a server that isn't known yet ("tofu" trust mode).
+pub type NewServerPrompt = fn(address: &str, fingerprint: &str) -> bool;
+
+// Trust on first use: the fingerprint of unknown servers is recorded once confirmed,
+// and any later change of certificate is rejected.
+struct KnownServersVerifier {
+    address: String,
+    known_servers: KnownServers,
+    new_server_prompt: NewServerPrompt,
+}
+
+impl ServerCertVerifier for KnownServersVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = rclip_config::certs::fingerprint(&end_entity.0);
+
+        match self.known_servers.find(&self.address).map_err(rustls::Error::General)? {
+            Some(known_server) if known_server.fingerprint == fingerprint => Ok(ServerCertVerified::assertion()),
+            Some(known_server) => {
+                eprintln!("WARNING: The certificate of server '{}' has changed!", self.address);
+                eprintln!("Someone could be intercepting the connection, or the server certificate was replaced.");
+                eprintln!("Expected fingerprint: {}", known_server.fingerprint);
+                eprintln!("Received fingerprint: {}", fingerprint);
+                eprintln!(
+                    "If the change is expected, remove the entry at line {} of '{}' and connect again.",
+                    known_server.line_number,
+                    self.known_servers.path.display()
+                );
+
+                Err(rustls::Error::General(format!(
+                    "Certificate verification failed for server '{}', its fingerprint differs from line {} of '{}'.",
+                    self.address,
+                    known_server.line_number,
+                    self.known_servers.path.display()
+                )))
+            }
+            None => {
+                if !(self.new_server_prompt)(&self.address, &fingerprint) {
+                    return Err(rustls::Error::General(format!(
+                        "The certificate of server '{}' was not accepted.",
+                        self.address
+                    )));
+                }
+
+                self.known_servers
+                    .add(&self.address, &fingerprint)
+                    .map_err(rustls::Error::General)?;
+
+                // Standard output may be the clipboard text of a paste
+                eprintln!(
+                    "Added server '{}' with fingerprint {} to '{}'.",
+                    self.address,
+                    fingerprint,
+                    self.known_servers.path.display()
+                );
+
+                Ok(ServerCertVerified::assertion())
+            }
+        }
+    }
+}
+
+// Accepts the server whose fingerprint starts like the one carried by a pairing code.
+struct PairingVerifier {
+    code: PairingCode,
+}
+
+impl ServerCertVerifier for PairingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = rclip_config::certs::fingerprint(&end_entity.0);
+
+        if self.code.matches_fingerprint(&fingerprint) {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        Err(rustls::Error::General(format!(
+            "The server certificate doesn't match the pairing code, its fingerprint is {}.",
+            fingerprint
+        )))
+    }
+}
+
+// The rustls verifier only supports DNS names, while rclip servers are often reached by IP address.
+struct CaVerifier {
+    roots: Vec<Vec<u8>>,
+}
+
+impl CaVerifier {
+    fn new(ca_file: Option<&str>) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let roots = match ca_file {
+            Some(path) => rclip_config::certs::load_certificates(path)?,
+            None => rustls_native_certs::load_native_certs()
+                .map_err(|e| format!("Could not load the system root certificates. {}", e))?
+                .into_iter()
+                .map(|cert| cert.0)
+                .collect(),
+        };
+
+        let roots: Vec<Vec<u8>> = roots
+            .into_iter()
+            .filter(|der| webpki::TrustAnchor::try_from_cert_der(der).is_ok())
+            .collect();
+
+        if roots.is_empty() {
+            return match ca_file {
+                Some(path) => Err(format!("No usable CA certificate found in '{}'.", path).into()),
+                None => Err("No usable CA certificate found in the system roots.".into()),
+            };
+        }
+
+        Ok(Self { roots })
+    }
+}
+
+impl ServerCertVerifier for CaVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let anchors: Vec<webpki::TrustAnchor> = self
+            .roots
+            .iter()
+            .filter_map(|der| webpki::TrustAnchor::try_from_cert_der(der).ok())
+            .collect();
+        let chain: Vec<&[u8]> = intermediates.iter().map(|cert| cert.0.as_slice()).collect();
+        let webpki_now = webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_slice()).map_err(pki_error)?;
+
+        cert.verify_is_valid_tls_server_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TlsServerTrustAnchors(&anchors),
+            &chain,
+            webpki_now,
+        )
+        .map_err(pki_error)?;
+
+        match server_name {
+            ServerName::DnsName(dns_name) => {
+                let dns_name = webpki::DnsNameRef::try_from_ascii_str(dns_name.as_ref())
+                    .map_err(|_| rustls::Error::UnsupportedNameType)?;
+                cert.verify_is_valid_for_dns_name(dns_name).map_err(pki_error)?;
+            }
+            ServerName::IpAddress(ip) => verify_ip_address(&end_entity.0, ip)?,
+            _ => return Err(rustls::Error::UnsupportedNameType),
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+fn verify_ip_address(cert_der: &[u8], ip: &IpAddr) -> Result<(), rustls::Error> {
+    use x509_parser::extensions::GeneralName;
+
+    let (_, cert) = x509_parser::parse_x509_certificate(cert_der)
+        .map_err(|_| rustls::Error::InvalidCertificateEncoding)?;
+    let san = cert
+        .subject_alternative_name()
+        .map_err(|_| rustls::Error::InvalidCertificateEncoding)?;
+
+    let ip_bytes = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+
+    if let Some(san) = san {
+        for name in &san.value.general_names {
+            if let GeneralName::IPAddress(bytes) = name {
+                if *bytes == ip_bytes.as_slice() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Err(rustls::Error::InvalidCertificateData(format!(
+        "invalid peer certificate: not valid for IP address {}",
+        ip
+    )))
+}
+
+fn pki_error(error: webpki::Error) -> rustls::Error {
+    use webpki::Error::*;
+
+    match error {
+        BadDer | BadDerTime => rustls::Error::InvalidCertificateEncoding,
+        InvalidSignatureForPublicKey => rustls::Error::InvalidCertificateSignature,
+        UnsupportedSignatureAlgorithm | UnsupportedSignatureAlgorithmForPublicKey => {
+            rustls::Error::InvalidCertificateSignatureType
+        }
+        e => rustls::Error::InvalidCertificateData(format!("invalid peer certificate: {}", e)),
+    }
+}
+
+// Returns the TLS configuration and the name to verify for a server.
+pub fn tls_config(
+    server_host: &str,
+    port_number: u32,
+    certificate: &rclip_config::ClientCertificate,
+    tls_policy: &rclip_config::tls::TlsPolicy,
+    new_server_prompt: NewServerPrompt,
+) -> Result<(rustls::ClientConfig, ServerName), Box<dyn Error + Send + Sync>> {
+    let trust_mode = certificate
+        .trust_mode
+        .as_deref()
+        .unwrap_or(rclip_config::TRUST_MODE_PINNED);
+
+    let address = rclip_config::format_address(server_host, port_number);
+
+    // What the server is trusted for, so that sessions aren't resumed once the trust settings change
+    let (verifier, trusted): (Arc<dyn ServerCertVerifier>, String) = match trust_mode {
+        rclip_config::TRUST_MODE_PINNED => {
+            let mut fingerprints = Vec::new();
+
+            for fingerprint in certificate.fingerprints.iter().flatten() {
+                fingerprints.push(rclip_config::certs::normalize_fingerprint(fingerprint)?);
+            }
+
+            // The certificate file is optional when fingerprints are configured
+            if let Some(key_pub_loc) = certificate.der_cert_pub.as_deref() {
+                if !Path::new(key_pub_loc).exists() {
+                    if fingerprints.is_empty() {
+                        return Err(format!("Cannot find public key at: {}", key_pub_loc).into());
+                    }
+                } else {
+                    // Only the first certificate of a chain identifies the server
+                    let key_pub_bytes = rclip_config::certs::load_certificates(key_pub_loc)?.remove(0);
+                    fingerprints.push(rclip_config::certs::fingerprint(&key_pub_bytes));
+                }
+            }
+
+            if fingerprints.is_empty() {
+                return Err("Please provide the public certificate argument for --der-cert-pub, or a --fingerprint.".into());
+            }
+
+            let trusted = fingerprints.join(",");
+
+            (Arc::new(AcceptSpecificCertsVerifier { fingerprints }), trusted)
+        }
+        rclip_config::TRUST_MODE_CA => {
+            let trusted = match certificate.ca_file.as_deref() {
+                Some(ca_file) => rclip_config::certs::fingerprint(&fs::read(ca_file).unwrap_or_default()),
+                None => "system".to_string(),
+            };
+
+            (Arc::new(CaVerifier::new(certificate.ca_file.as_deref())?), trusted)
+        }
+        rclip_config::TRUST_MODE_TOFU => {
+            let known_servers = KnownServers::open_default()?;
+            let trusted = match known_servers.find(&address)? {
+                Some(known_server) => known_server.fingerprint,
+                None => String::new(),
+            };
+
+            let verifier = KnownServersVerifier {
+                address: address.clone(),
+                known_servers,
+                new_server_prompt,
+            };
+
+            (Arc::new(verifier), trusted)
+        }
+        _ => return Err(format!("Unknown trust mode '{}'.", trust_mode).into()),
+    };
+
+    let server_name = match (&certificate.server_name, trust_mode) {
+        (Some(server_name), _) => server_name.as_str(),
+        (None, rclip_config::TRUST_MODE_CA) => {
+            if rclip_config::is_path_address(server_host) || server_host.starts_with(rclip_config::VSOCK_PREFIX) {
+                return Err(format!(
+                    "A server name is required to verify the certificate of '{}', please provide --server-name.",
+                    server_host
+                )
+                .into());
+            }
+
+            rclip_config::unbracket_host(server_host)
+        }
+        // Any name will do, as only the certificate itself is checked
+        (None, _) => "localhost",
+    };
+
+    let session_scope = format!(
+        "{} {} {} {} {}",
+        address,
+        trust_mode,
+        server_name,
+        trusted,
+        policy_scope(tls_policy)
+    );
+
+    let server_name = ServerName::try_from(server_name)
+        .map_err(|_| format!("Invalid server name '{}'.", server_name))?;
+
+    let builder = rclip_config::tls::apply(rustls::ClientConfig::builder(), tls_policy)?
+        .with_custom_certificate_verifier(verifier);
+
+    // Sessions are bound to the client certificate they were established with
+    let (mut config, client_identity) = match (&certificate.client_cert, &certificate.client_key) {
+        (Some(client_cert), Some(client_key)) => {
+            let certs: Vec<Certificate> = rclip_config::certs::load_certificates(client_cert)?
+                .into_iter()
+                .map(Certificate)
+                .collect();
+            let key = rustls::PrivateKey(rclip_config::certs::load_private_key(client_key)?);
+            let client_identity = certs
+                .first()
+                .map(|cert| rclip_config::certs::fingerprint(&cert.0))
+                .unwrap_or_default();
+
+            let config = builder
+                .with_single_cert(certs, key)
+                .map_err(|e| format!("Invalid client certificate or key. {}", e))?;
+
+            (config, client_identity)
+        }
+        (None, None) => (builder.with_no_client_auth(), "-".to_string()),
+        _ => return Err("Both the client certificate and its private key are required.".into()),
+    };
+
+    let session_scope = format!("{} {}", session_scope, client_identity);
+
+    // Resumption is only an optimization, sessions stay in memory if the data directory is unavailable
+    if let Ok(session_store) = PersistentSessionStore::open_default(&rclip_config::certs::fingerprint(session_scope.as_bytes())) {
+        config.session_storage = Arc::new(session_store);
+    }
+
+    Ok((config, server_name))
+}
+
+// TLS versions, cipher suites and key exchange groups, as sessions are only resumed with the policy they were established with.
+fn policy_scope(tls_policy: &rclip_config::tls::TlsPolicy) -> String {
+    let values = |values: &Option<Vec<String>>| values.as_ref().map(|values| values.join(",")).unwrap_or_else(|| "-".to_string());
+
+    format!(
+        "{}/{}/{}",
+        values(&tls_policy.versions),
+        values(&tls_policy.cipher_suites),
+        values(&tls_policy.kx_groups)
+    )
+}
+
+// Returns the TLS configuration used to pair with a server, which isn't trusted yet.
+pub fn pairing_tls_config(
+    code: PairingCode,
+    tls_policy: &rclip_config::tls::TlsPolicy,
+) -> Result<(rustls::ClientConfig, ServerName), Box<dyn Error + Send + Sync>> {
+    let config = rclip_config::tls::apply(rustls::ClientConfig::builder(), tls_policy)?
+        .with_custom_certificate_verifier(Arc::new(PairingVerifier { code }))
+        .with_no_client_auth();
+
+    // Any name will do, as only the certificate itself is checked
+    let server_name = ServerName::try_from("localhost").map_err(|_| "Invalid server name 'localhost'.")?;
+
+    Ok((config, server_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known_servers(name: &str) -> KnownServers {
+        let path = std::env::temp_dir().join(format!("rclip-known-servers-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        KnownServers { path }
+    }
+
+    fn verify(verifier: &KnownServersVerifier, cert: &[u8]) -> Result<ServerCertVerified, rustls::Error> {
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let cert = Certificate(cert.to_vec());
+
+        verifier.verify_server_cert(&cert, &[], &server_name, &mut std::iter::empty(), &[], SystemTime::now())
+    }
+
+    #[test]
+    fn trusts_servers_on_first_use() {
+        let verifier = KnownServersVerifier {
+            address: "127.0.0.1:10080".to_string(),
+            known_servers: known_servers("tofu"),
+            new_server_prompt: |_, _| true,
+        };
+        let fingerprint = rclip_config::certs::fingerprint(b"first certificate");
+
+        // First use records the fingerprint, which then has to match
+        assert!(verify(&verifier, b"first certificate").is_ok());
+        let contents = fs::read_to_string(&verifier.known_servers.path).unwrap();
+        assert_eq!(contents, format!("127.0.0.1:10080 {}\n", fingerprint));
+
+        assert!(verify(&verifier, b"first certificate").is_ok());
+
+        let err = verify(&verifier, b"other certificate").unwrap_err();
+        assert!(err.to_string().contains("differs from line 1"), "{}", err);
+        assert_eq!(fs::read_to_string(&verifier.known_servers.path).unwrap(), contents);
+
+        let _ = fs::remove_file(&verifier.known_servers.path);
+    }
+
+    #[test]
+    fn records_nothing_when_declined() {
+        let verifier = KnownServersVerifier {
+            address: "127.0.0.1:10080".to_string(),
+            known_servers: known_servers("declined"),
+            new_server_prompt: |_, _| false,
+        };
+
+        assert!(verify(&verifier, b"first certificate").is_err());
+        assert!(!verifier.known_servers.path.exists());
+    }
+}
@@ -0,0 +1,60 @@
+// XDG_DATA_HOME only moves the data directory of the client on Linux
+#![cfg(all(feature = "async", target_os = "linux"))]
+
+use rclip_client_lib::{BlockingPoolClient, ClientError};
+use rclip_server::{certgen, ListenerOptions, LocalAddr, RclipServer, ServerTls};
+
+const MAX_SIZE: usize = 64;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_writes_and_clears() {
+    // The client keeps its TLS sessions in the data directory, kept apart from the user's own
+    let data_dir = std::env::temp_dir().join(format!("rclip-client-lib-test-{}", std::process::id()));
+    std::env::set_var("XDG_DATA_HOME", &data_dir);
+
+    let sans = ["127.0.0.1".to_string()];
+    let (cert_der, key_der) = certgen::generate(certgen::KEY_TYPE_ECDSA, 0, &sans, 1).unwrap();
+    let fingerprint = rclip_config::certs::fingerprint(&cert_der);
+
+    let server = RclipServer::builder()
+        .tls(ServerTls::from_der(vec![cert_der], key_der))
+        .listener(ListenerOptions::new("127.0.0.1", 0))
+        .max_size(MAX_SIZE)
+        .spawn()
+        .await
+        .unwrap();
+
+    let port = match server.local_addrs() {
+        [LocalAddr::Tcp(addr)] => addr.port(),
+        _ => panic!("Expected a single TCP listener."),
+    };
+
+    let mut config = rclip_config::ClientConfig::default();
+    config.server.host = Some("127.0.0.1".to_string());
+    config.server.port = Some(port.into());
+    config.certificate.fingerprints = Some(vec![fingerprint.clone()]);
+    let client = BlockingPoolClient::connect(config).unwrap();
+
+    assert_eq!(client.address(), format!("127.0.0.1:{}", port));
+    assert_eq!(client.read().await.unwrap(), "");
+
+    client.write("some text").await.unwrap();
+    assert_eq!(client.read().await.unwrap(), "some text");
+    assert_eq!(&server.clipboard().read().unwrap()[..], b"some text");
+
+    client.clear().await.unwrap();
+    assert_eq!(client.read().await.unwrap(), "");
+
+    let status = client.status().await.unwrap();
+    assert_eq!(rclip_client_lib::status_value(&status, "fingerprint"), Some(fingerprint.as_str()));
+
+    let too_large = "x".repeat(MAX_SIZE);
+    assert!(matches!(client.write(&too_large).await, Err(ClientError::Limit(_))));
+
+    server.stop();
+    server.wait().await.unwrap();
+
+    assert!(matches!(client.read().await, Err(ClientError::Connection(_))));
+
+    let _ = std::fs::remove_dir_all(data_dir);
+}
@@ -0,0 +1,242 @@
+// Connection bundles, as exported by `rclip-server export-client-bundle`: everything a client needs
+// to reach a server, either as a TOML file or as an rclip://host:port?fp=sha256:...&token=... URI.
+// Only files can embed the server certificate, URIs pin its fingerprint.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::BufReader;
+use std::net::Ipv6Addr;
+
+use crate::{percent, Server};
+
+pub const URI_SCHEME: &str = "rclip://";
+
+const PARAM_FINGERPRINT: &str = "fp";
+const PARAM_TOKEN: &str       = "token";
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ClientBundle {
+    #[serde(rename(deserialize = "fingerprint", serialize = "fingerprint"))]
+    pub fingerprints: Vec<String>,
+    // PEM encoded server certificate
+    pub certificate: Option<String>,
+    pub token: Option<String>,
+    // Tables come last in TOML documents
+    pub server: Server,
+}
+
+impl ClientBundle {
+    // Reads a bundle from either an rclip:// URI or a file.
+    pub fn load(source: &str) -> Result<Self, String> {
+        let source = source.trim();
+
+        if source.starts_with(URI_SCHEME) {
+            return Self::from_uri(source);
+        }
+
+        let data = fs::read_to_string(source).map_err(|e| format!("Could not read '{}'. {}", source, e))?;
+
+        let ret: Self = toml::from_str(&data)
+            .map_err(|e| format!("'{}' is not a valid connection bundle. {}", source, e))?;
+
+        ret.validate()?;
+
+        Ok(ret)
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| format!("Could not serialize connection bundle. {}", e))
+    }
+
+    pub fn to_uri(&self) -> Result<String, String> {
+        let (host, port) = self.address()?;
+        let mut params: Vec<String> = self
+            .fingerprints
+            .iter()
+            .map(|fingerprint| format!("{}={}", PARAM_FINGERPRINT, fingerprint))
+            .collect();
+
+        if let Some(token) = &self.token {
+            params.push(format!("{}={}", PARAM_TOKEN, token));
+        }
+
+        // IPv6 literals may have a zone, i.e. "fe80::1%eth0"
+        let host = crate::unbracket_host(&host);
+        let address = match host.split('%').next().unwrap_or_default().parse::<Ipv6Addr>() {
+            Ok(_) => format!("[{}]:{}", percent::encode(host), port),
+            Err(_) => crate::format_address(&percent::encode(host), port),
+        };
+
+        Ok(format!("{}{}?{}", URI_SCHEME, address, params.join("&")))
+    }
+
+    pub fn from_uri(uri: &str) -> Result<Self, String> {
+        let rest = uri
+            .strip_prefix(URI_SCHEME)
+            .ok_or_else(|| format!("Invalid connection URI '{}', expecting '{}'.", uri, URI_SCHEME))?;
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (host, port) = crate::parse_address(address.trim_end_matches('/')).map_err(|e| e.to_string())?;
+
+        // Anything else would have been escaped by to_uri, and may belong to the query or a path
+        let escaped = host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~' | ':' | '%'));
+        let host = percent::decode(&host);
+
+        if !escaped || !is_valid_host(&host) {
+            return Err(format!("Invalid server host in connection URI '{}'.", uri));
+        }
+
+        let mut ret = Self {
+            server: Server {
+                host: Some(host),
+                port: port.or(Some(crate::DEFAULT_SERVER_PORT)),
+            },
+            ..Default::default()
+        };
+
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            // Tokens and fingerprints don't need escaping, but URIs may have been escaped along the way
+            let value = percent::decode(value);
+
+            match name {
+                PARAM_FINGERPRINT => ret.fingerprints.push(value),
+                PARAM_TOKEN => ret.token = Some(value),
+                _ => return Err(format!("Unknown parameter '{}' in connection URI.", name)),
+            }
+        }
+
+        ret.validate()?;
+
+        Ok(ret)
+    }
+
+    // Returns the DER encoded certificate, if the bundle embeds one.
+    pub fn certificate_der(&self) -> Result<Option<Vec<u8>>, String> {
+        let pem = match &self.certificate {
+            Some(pem) => pem,
+            None => return Ok(None),
+        };
+
+        let mut certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_bytes()))
+            .map_err(|e| format!("Invalid certificate in connection bundle. {}", e))?;
+
+        if certs.is_empty() {
+            return Err("No certificate found in connection bundle.".to_string());
+        }
+
+        Ok(Some(certs.remove(0)))
+    }
+
+    // Checks that the server can be reached and identified.
+    pub fn validate(&self) -> Result<(), String> {
+        self.address()?;
+
+        for fingerprint in &self.fingerprints {
+            crate::certs::normalize_fingerprint(fingerprint)?;
+        }
+
+        if self.fingerprints.is_empty() && self.certificate_der()?.is_none() {
+            return Err("The connection bundle has neither a certificate nor a fingerprint.".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn address(&self) -> Result<(String, u32), String> {
+        match (&self.server.host, self.server.port) {
+            (Some(host), Some(port)) => Ok((host.clone(), port)),
+            _ => Err("The connection bundle is missing the server host or port.".to_string()),
+        }
+    }
+}
+
+// Network hosts are names or IP addresses (with an optional zone, i.e. "fe80::1%eth0"), paths can be anything printable.
+fn is_valid_host(host: &str) -> bool {
+    if crate::is_path_address(host) {
+        return !host.chars().any(char::is_control);
+    }
+
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | ':' | '%'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "sha256:11e653262d325306d06e878202f0f741b8de6611e8fe4207984cf2bea6e16f66";
+
+    fn bundle(host: &str, port: u32) -> ClientBundle {
+        ClientBundle {
+            fingerprints: vec![FINGERPRINT.to_string()],
+            token: Some("c2VjcmV0".to_string()),
+            server: Server {
+                host: Some(host.to_string()),
+                port: Some(port),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trips_uris() {
+        for (host, port) in [("example.org", 10080), ("::1", 10081), ("vsock:host", 10082), ("fe80::1%eth0", 10083)] {
+            let uri = bundle(host, port).to_uri().unwrap();
+            let parsed = ClientBundle::from_uri(&uri).unwrap();
+
+            assert_eq!(parsed.server.host.as_deref(), Some(host), "{}", uri);
+            assert_eq!(parsed.server.port, Some(port), "{}", uri);
+            assert_eq!(parsed.fingerprints, vec![FINGERPRINT.to_string()]);
+            assert_eq!(parsed.token.as_deref(), Some("c2VjcmV0"));
+        }
+
+        let expected = format!("rclip://[::1]:10081?fp={}&token=c2VjcmV0", FINGERPRINT);
+        assert_eq!(bundle("::1", 10081).to_uri().unwrap(), expected);
+
+        // Paths don't carry a port
+        let uri = bundle("unix:/run/rclip 1.sock", 10080).to_uri().unwrap();
+        assert_eq!(uri, format!("rclip://unix:%2Frun%2Frclip%201.sock?fp={}&token=c2VjcmV0", FINGERPRINT));
+        let parsed = ClientBundle::from_uri(&uri).unwrap();
+        assert_eq!(parsed.server.host.as_deref(), Some("unix:/run/rclip 1.sock"));
+    }
+
+    #[test]
+    fn parses_escaped_uris() {
+        let uri = format!("rclip://example.org/?fp={}&token=c2VjcmV0%3D%3d", FINGERPRINT.replace(':', "%3A"));
+        let parsed = ClientBundle::from_uri(&uri).unwrap();
+
+        assert_eq!(parsed.server.port, Some(crate::DEFAULT_SERVER_PORT));
+        assert_eq!(parsed.fingerprints, vec![FINGERPRINT.to_string()]);
+        assert_eq!(parsed.token.as_deref(), Some("c2VjcmV0=="));
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        assert!(ClientBundle::from_uri("https://example.org").is_err());
+        assert!(ClientBundle::from_uri("rclip://example.org").is_err());
+        assert!(ClientBundle::from_uri("rclip://example.org?fp=sha256:1234").is_err());
+        assert!(ClientBundle::from_uri(&format!("rclip://example.org?fp={}&user=me", FINGERPRINT)).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_hosts() {
+        for host in ["example.org/path", "exam&ple.org", "example.org#top", "me@example.org", "", "exa%2Fmple.org", "example.org%3Bid", "%20"] {
+            let uri = format!("rclip://{}:10080?fp={}", host, FINGERPRINT);
+            assert_eq!(
+                ClientBundle::from_uri(&uri).err(),
+                Some(format!("Invalid server host in connection URI '{}'.", uri)),
+                "{}",
+                host
+            );
+        }
+
+        // Escaped zones of IPv6 link-local addresses are kept
+        let parsed = ClientBundle::from_uri(&format!("rclip://[fe80::1%25eth0]:10080?fp={}", FINGERPRINT)).unwrap();
+        assert_eq!(parsed.server.host.as_deref(), Some("fe80::1%eth0"));
+    }
+}
@@ -0,0 +1,399 @@
+// Loading of certificates and private keys in either PEM or DER format.
+// The format and the kind of data are detected from the file contents, so that
+// files from an existing infrastructure can be used without any conversion.
+
+use std::fmt;
+use std::fs;
+use std::io::BufReader;
+
+type Items = Vec<(DataKind, Vec<u8>)>;
+
+pub const FINGERPRINT_PREFIX: &str = "sha256:";
+
+const PEM_BEGIN: &str = "-----BEGIN ";
+const PEM_LINE_LEN: usize = 64;
+
+const DER_TAG_INTEGER: u8      = 0x02;
+const DER_TAG_OCTET_STRING: u8 = 0x04;
+const DER_TAG_SEQUENCE: u8     = 0x30;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DataKind {
+    Certificate,
+    Pkcs1Key,
+    Pkcs8Key,
+    Sec1Key,
+    Crl,
+}
+
+impl DataKind {
+    fn is_private_key(&self) -> bool {
+        matches!(self, DataKind::Pkcs1Key | DataKind::Pkcs8Key | DataKind::Sec1Key)
+    }
+}
+
+impl fmt::Display for DataKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataKind::Certificate => write!(f, "an X.509 certificate"),
+            DataKind::Pkcs1Key => write!(f, "a PKCS#1 RSA private key"),
+            DataKind::Pkcs8Key => write!(f, "a PKCS#8 private key"),
+            DataKind::Sec1Key => write!(f, "a SEC1 EC private key"),
+            DataKind::Crl => write!(f, "a certificate revocation list"),
+        }
+    }
+}
+
+// Returns the DER encoded certificates of a file, the server certificate coming first in chains.
+pub fn load_certificates(path: &str) -> Result<Vec<Vec<u8>>, String> {
+    let items = load_items(path)?;
+    let mut certs = Vec::new();
+    let mut others = Vec::new();
+
+    for (kind, der) in items {
+        if kind == DataKind::Certificate {
+            certs.push(der);
+        } else {
+            others.push(kind);
+        }
+    }
+
+    if certs.is_empty() {
+        return match others.first() {
+            Some(kind) => Err(format!("'{}' is not a certificate, it contains {}.", path, kind)),
+            None => Err(format!("'{}' doesn't contain any certificate.", path)),
+        };
+    }
+
+    Ok(certs)
+}
+
+// Returns a DER encoded PKCS#1, PKCS#8 or SEC1 private key, as accepted by rustls.
+pub fn load_private_key(path: &str) -> Result<Vec<u8>, String> {
+    let items = load_items(path)?;
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    let mut others = Vec::new();
+
+    for (kind, der) in items {
+        if kind.is_private_key() {
+            keys.push(der);
+        } else {
+            others.push(kind);
+        }
+    }
+
+    match keys.len() {
+        1 => Ok(keys.remove(0)),
+        0 => match others.first() {
+            Some(kind) => Err(format!("'{}' is not a private key, it contains {}.", path, kind)),
+            None => Err(format!("'{}' doesn't contain any private key.", path)),
+        },
+        n => Err(format!("'{}' contains {} private keys, only one is expected.", path, n)),
+    }
+}
+
+// Returns the SHA-256 fingerprint of a DER encoded certificate, i.e. "sha256:3f9a...".
+pub fn fingerprint(cert_der: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, cert_der);
+    let hex: String = digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+
+    format!("{}{}", FINGERPRINT_PREFIX, hex)
+}
+
+// Returns a DER encoded certificate in PEM format.
+pub fn to_pem(cert_der: &[u8]) -> String {
+    use base64::Engine;
+
+    let encoded = base64::engine::general_purpose::STANDARD.encode(cert_der);
+    let mut ret = String::from("-----BEGIN CERTIFICATE-----\n");
+
+    for line in encoded.as_bytes().chunks(PEM_LINE_LEN) {
+        ret.push_str(&String::from_utf8_lossy(line));
+        ret.push('\n');
+    }
+
+    ret.push_str("-----END CERTIFICATE-----\n");
+    ret
+}
+
+// Returns the canonical form of a "sha256:..." fingerprint. Hex digits may be uppercase
+// and separated by colons, as printed by `openssl x509 -fingerprint -sha256`.
+pub fn normalize_fingerprint(value: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid fingerprint '{}', expecting '{}' followed by 64 hex digits.", value, FINGERPRINT_PREFIX);
+
+    let value_trimmed = value.trim();
+
+    let hex = match value_trimmed.get(..FINGERPRINT_PREFIX.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(FINGERPRINT_PREFIX) => {
+            value_trimmed[FINGERPRINT_PREFIX.len()..].replace(':', "")
+        }
+        _ => return Err(invalid()),
+    };
+
+    if hex.len() != ring::digest::SHA256_OUTPUT_LEN * 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+
+    Ok(format!("{}{}", FINGERPRINT_PREFIX, hex.to_ascii_lowercase()))
+}
+
+fn load_items(path: &str) -> Result<Items, String> {
+    let data = fs::read(path).map_err(|e| format!("Could not read '{}'. {}", path, e))?;
+
+    if data.iter().all(|b| b.is_ascii_whitespace()) {
+        return Err(format!("'{}' is empty.", path));
+    }
+
+    if is_pem(&data) {
+        parse_pem(path, &data)
+    } else {
+        parse_der(path, &data)
+    }
+}
+
+fn is_pem(data: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(data);
+    text.trim_start().starts_with(PEM_BEGIN) || text.contains(&format!("\n{}", PEM_BEGIN))
+}
+
+fn parse_pem(path: &str, data: &[u8]) -> Result<Items, String> {
+    use rustls_pemfile::Item;
+
+    let items = rustls_pemfile::read_all(&mut BufReader::new(data))
+        .map_err(|e| format!("'{}' is not a valid PEM file. {}", path, e))?;
+
+    let ret: Items = items
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some((DataKind::Certificate, der)),
+            Item::RSAKey(der) => Some((DataKind::Pkcs1Key, der)),
+            Item::PKCS8Key(der) => Some((DataKind::Pkcs8Key, der)),
+            Item::ECKey(der) => Some((DataKind::Sec1Key, der)),
+            Item::Crl(der) => Some((DataKind::Crl, der)),
+            _ => None,
+        })
+        .collect();
+
+    if ret.is_empty() {
+        // i.e. encrypted keys or certificate requests
+        let labels: Vec<String> = String::from_utf8_lossy(data)
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix(PEM_BEGIN))
+            .map(|label| label.trim_end_matches('-').to_string())
+            .collect();
+
+        return Err(format!("'{}' contains unsupported PEM data: {}.", path, labels.join(", ")));
+    }
+
+    Ok(ret)
+}
+
+// A DER file holds one or more consecutive structures (i.e. a certificate chain).
+fn parse_der(path: &str, data: &[u8]) -> Result<Items, String> {
+    let mut ret = Vec::new();
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let invalid = || format!("'{}' is neither a PEM file nor a valid DER file.", path);
+
+        let (tag, _, len) = read_tlv(rest).ok_or_else(invalid)?;
+
+        if tag != DER_TAG_SEQUENCE {
+            return Err(invalid());
+        }
+
+        let kind = sniff_der(&rest[..len]).ok_or_else(|| {
+            format!("'{}' contains DER data that is neither a certificate nor a private key.", path)
+        })?;
+
+        ret.push((kind, rest[..len].to_vec()));
+        rest = &rest[len..];
+    }
+
+    Ok(ret)
+}
+
+// Tells structures apart from their first fields:
+// - Certificate: SEQUENCE { SEQUENCE tbsCertificate, ... }
+// - PKCS#1:      SEQUENCE { INTEGER version, INTEGER modulus, ... }
+// - PKCS#8:      SEQUENCE { INTEGER version, SEQUENCE algorithm, OCTET STRING key, ... }
+// - SEC1:        SEQUENCE { INTEGER 1, OCTET STRING key, ... }
+fn sniff_der(data: &[u8]) -> Option<DataKind> {
+    let (_, header_len, _) = read_tlv(data)?;
+    let content = &data[header_len..];
+
+    let (first_tag, _, first_len) = read_tlv(content)?;
+    let (second_tag, _, _) = read_tlv(&content[first_len..])?;
+
+    match (first_tag, second_tag) {
+        (DER_TAG_SEQUENCE, DER_TAG_SEQUENCE) => Some(DataKind::Certificate),
+        (DER_TAG_INTEGER, DER_TAG_INTEGER) => Some(DataKind::Pkcs1Key),
+        (DER_TAG_INTEGER, DER_TAG_SEQUENCE) => Some(DataKind::Pkcs8Key),
+        (DER_TAG_INTEGER, DER_TAG_OCTET_STRING) => Some(DataKind::Sec1Key),
+        _ => None,
+    }
+}
+
+// Returns the tag, the header length and the total length of the first element.
+fn read_tlv(data: &[u8]) -> Option<(u8, usize, usize)> {
+    let tag = *data.first()?;
+    let first_len_byte = *data.get(1)?;
+
+    let (header_len, content_len) = if first_len_byte & 0x80 == 0 {
+        (2, first_len_byte as usize)
+    } else {
+        let len_bytes = (first_len_byte & 0x7f) as usize;
+
+        if len_bytes == 0 || len_bytes > 4 {
+            return None;
+        }
+
+        let content_len = data
+            .get(2..2 + len_bytes)?
+            .iter()
+            .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+
+        (2 + len_bytes, content_len)
+    };
+
+    let total_len = header_len.checked_add(content_len)?;
+
+    if total_len > data.len() {
+        return None;
+    }
+
+    Some((tag, header_len, total_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    struct Fixtures {
+        dir: PathBuf,
+        cert_der: Vec<u8>,
+        cert_pem: String,
+        key_der: Vec<u8>,
+        key_pem: String,
+    }
+
+    impl Fixtures {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rclip-config-test-certs-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+
+            let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+            // Each serialization signs the certificate again, with a different ECDSA signature
+            let cert_der = cert.serialize_der().unwrap();
+
+            Self {
+                dir,
+                cert_pem: to_pem(&cert_der),
+                cert_der,
+                key_der: cert.serialize_private_key_der(),
+                key_pem: cert.serialize_private_key_pem(),
+            }
+        }
+
+        fn write(&self, name: &str, contents: &[u8]) -> String {
+            let path = self.dir.join(name);
+            fs::write(&path, contents).unwrap();
+
+            path.display().to_string()
+        }
+    }
+
+    impl Drop for Fixtures {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn loads_pem_and_der_files() {
+        let fixtures = Fixtures::new("formats");
+
+        let path = fixtures.write("cert.pem", fixtures.cert_pem.as_bytes());
+        assert_eq!(load_certificates(&path).unwrap(), vec![fixtures.cert_der.clone()]);
+
+        let path = fixtures.write("cert.der", &fixtures.cert_der);
+        assert_eq!(load_certificates(&path).unwrap(), vec![fixtures.cert_der.clone()]);
+
+        let path = fixtures.write("key.pem", fixtures.key_pem.as_bytes());
+        assert_eq!(load_private_key(&path).unwrap(), fixtures.key_der);
+
+        let path = fixtures.write("key.der", &fixtures.key_der);
+        assert_eq!(load_private_key(&path).unwrap(), fixtures.key_der);
+    }
+
+    #[test]
+    fn loads_pem_bundles() {
+        let fixtures = Fixtures::new("bundles");
+        let other = Fixtures::new("bundles-other");
+
+        // A chain followed by its key, with text in between as printed by openssl
+        let bundle = format!("subject=CN = rclip\n{}{}\n{}", fixtures.cert_pem, other.cert_pem, fixtures.key_pem);
+        let path = fixtures.write("bundle.pem", bundle.as_bytes());
+
+        assert_eq!(load_certificates(&path).unwrap(), vec![fixtures.cert_der.clone(), other.cert_der.clone()]);
+        assert_eq!(load_private_key(&path).unwrap(), fixtures.key_der);
+
+        let keys = format!("{}{}", fixtures.key_pem, other.key_pem);
+        let path = fixtures.write("keys.pem", keys.as_bytes());
+        assert_eq!(
+            load_private_key(&path).unwrap_err(),
+            format!("'{}' contains 2 private keys, only one is expected.", path)
+        );
+
+        let path = fixtures.write("chain.der", &[fixtures.cert_der.clone(), other.cert_der.clone()].concat());
+        assert_eq!(load_certificates(&path).unwrap(), vec![fixtures.cert_der.clone(), other.cert_der.clone()]);
+    }
+
+    #[test]
+    fn reports_empty_and_invalid_files() {
+        let fixtures = Fixtures::new("invalid");
+
+        let path = fixtures.write("empty.pem", b" \n");
+        assert_eq!(load_certificates(&path).unwrap_err(), format!("'{}' is empty.", path));
+        assert_eq!(load_private_key(&path).unwrap_err(), format!("'{}' is empty.", path));
+
+        let path = fixtures.write("text.der", b"not a certificate");
+        assert_eq!(
+            load_certificates(&path).unwrap_err(),
+            format!("'{}' is neither a PEM file nor a valid DER file.", path)
+        );
+
+        let path = fixtures.write("request.pem", b"-----BEGIN CERTIFICATE REQUEST-----\nMIIB\n-----END CERTIFICATE REQUEST-----\n");
+        assert_eq!(
+            load_certificates(&path).unwrap_err(),
+            format!("'{}' contains unsupported PEM data: CERTIFICATE REQUEST.", path)
+        );
+
+        let path = fixtures.dir.join("missing.pem").display().to_string();
+        assert!(load_certificates(&path).unwrap_err().starts_with(&format!("Could not read '{}'.", path)));
+    }
+
+    #[test]
+    fn reports_swapped_certificates_and_keys() {
+        let fixtures = Fixtures::new("swapped");
+
+        for (name, contents) in [("key.pem", fixtures.key_pem.as_bytes()), ("key.der", &fixtures.key_der[..])] {
+            let path = fixtures.write(name, contents);
+            assert_eq!(
+                load_certificates(&path).unwrap_err(),
+                format!("'{}' is not a certificate, it contains a PKCS#8 private key.", path)
+            );
+        }
+
+        for (name, contents) in [("cert.pem", fixtures.cert_pem.as_bytes()), ("cert.der", &fixtures.cert_der[..])] {
+            let path = fixtures.write(name, contents);
+            assert_eq!(
+                load_private_key(&path).unwrap_err(),
+                format!("'{}' is not a private key, it contains an X.509 certificate.", path)
+            );
+        }
+    }
+}
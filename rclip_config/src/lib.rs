@@ -1,19 +1,39 @@
 use serde::de::DeserializeOwned;
 use std::error::Error;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fs;
 use std::io::{Read, Write};
+use std::net::Ipv6Addr;
+use std::path::PathBuf;
+
+pub mod bundle;
+pub mod certs;
+pub mod pairing;
+mod percent;
+pub mod proxy;
+pub mod tls;
 
 pub const PROGRAM_GROUP: &str = "rclip";
 pub const DEFAULT_SERVER_HOST: &str = "127.0.0.1";
-pub const DEFAULT_SERVER_PORT: u16  = 10080;
+pub const DEFAULT_SERVER_PORT: u32  = 10080;
 pub const DEFAULT_FILENAME_DER_CERT_PUB:  &str = "der-cert-pub.der";
+pub const VSOCK_PREFIX: &str  = "vsock:";
+pub const UNIX_PREFIX: &str   = "unix:";
+pub const DEVICE_PREFIX: &str = "device:";
+pub const TRUST_MODE_PINNED: &str = "pinned";
+pub const TRUST_MODE_CA: &str     = "ca";
+pub const TRUST_MODE_TOFU: &str   = "tofu";
+
+// Well-known AF_VSOCK context identifiers, see vsock(7)
+pub const VSOCK_CID_ANY: u32   = u32::MAX;
+pub const VSOCK_CID_LOCAL: u32 = 1;
+pub const VSOCK_CID_HOST: u32  = 2;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct Server {
     pub host: Option<String>,
-    pub port: Option<u16>,
+    pub port: Option<u32>,
 }
 
 impl Default for Server {
@@ -25,23 +45,79 @@ impl Default for Server {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 #[serde(default)]
 pub struct ServerConfig {
     pub server: Server,
     pub certificate: ServerCertificate,
+    pub clipboard: ServerClipboard,
+    pub auth: ServerAuth,
+    pub rotation: ServerRotation,
+    pub tls: tls::TlsPolicy,
+    #[serde(rename(deserialize = "listener"))]
+    pub listeners: Vec<Listener>,
 }
 
-impl Default for ServerConfig {
-    fn default() -> Self {
-        Self {
-            server: Server::default(),
-            certificate: ServerCertificate::default(),
-        }
-    }
+// An additional address to listen on, with its own optional certificate.
+// Missing values are inherited from the top-level [server] and [certificate] sections.
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Listener {
+    pub host: Option<String>,
+    pub port: Option<u32>,
+    #[serde(rename(deserialize = "dual-stack", serialize = "dual-stack"))]
+    pub dual_stack: Option<bool>,
+    #[serde(rename(deserialize = "require-token", serialize = "require-token"))]
+    pub require_token: Option<bool>,
+    #[serde(rename(deserialize = "require-client-cert", serialize = "require-client-cert"))]
+    pub require_client_cert: Option<bool>,
+    pub certificate: Option<ServerCertificate>,
+}
+
+// The backend is either "memory" (default), "system" for the clipboard of the server machine, or "file"
+// for a text file surviving restarts (default with --stdio), the "clipboard" file of the data directory
+// unless another file is configured. Larger requests than max-size bytes are rejected (10 MiB by default).
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct ServerClipboard {
+    pub backend: Option<String>,
+    pub file: Option<String>,
+    #[serde(rename(deserialize = "max-size", serialize = "max-size"))]
+    pub max_size: Option<usize>,
 }
 
-#[derive(Deserialize, Serialize)]
+// Client tokens are issued when pairing, see `rclip-server pair`. Once required,
+// requests without a known token are rejected, except for pairing requests.
+// Client certificates are issued by `rclip-server ca issue`, unless another client CA is configured.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct ServerAuth {
+    #[serde(rename(deserialize = "require-token", serialize = "require-token"))]
+    pub require_token: Option<bool>,
+    #[serde(rename(deserialize = "require-client-cert", serialize = "require-client-cert"))]
+    pub require_client_cert: Option<bool>,
+    #[serde(rename(deserialize = "client-ca-file", serialize = "client-ca-file"))]
+    pub client_ca_file: Option<String>,
+}
+
+// Expiry warnings start warn-days before the end of the validity period. With auto-rotate, the next
+// self-managed certificate is generated prepare-days before, and served switch-days before.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct ServerRotation {
+    #[serde(rename(deserialize = "warn-days", serialize = "warn-days"))]
+    pub warn_days: Option<u32>,
+    #[serde(rename(deserialize = "auto-rotate", serialize = "auto-rotate"))]
+    pub auto_rotate: Option<bool>,
+    #[serde(rename(deserialize = "prepare-days", serialize = "prepare-days"))]
+    pub prepare_days: Option<u32>,
+    #[serde(rename(deserialize = "switch-days", serialize = "switch-days"))]
+    pub switch_days: Option<u32>,
+    #[serde(rename(deserialize = "validity-days", serialize = "validity-days"))]
+    pub validity_days: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 #[serde(default)]
 pub struct ServerCertificate {
     #[serde(rename(deserialize = "der-cert-pub", serialize = "der-cert-pub"))]
@@ -50,43 +126,170 @@ pub struct ServerCertificate {
     pub der_cert_priv: Option<String>,
 }
 
-impl Default for ServerCertificate {
-    fn default() -> Self {
-        Self {
-            der_cert_pub: None,
-            der_cert_priv: None,
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize)]
+// The trust mode is either "pinned" (default), accepting only the configured server certificate or fingerprints,
+// "tofu" to trust servers on first use, or "ca" for the standard verification against a CA bundle
+// (the system roots unless a CA file is set).
+#[derive(Serialize, Deserialize, Clone, Default)]
 #[serde(default)]
 pub struct ClientCertificate {
     #[serde(rename(deserialize = "der-cert-pub", serialize = "der-cert-pub"))]
     pub der_cert_pub: Option<String>,
+    #[serde(rename(deserialize = "trust-mode", serialize = "trust-mode"))]
+    pub trust_mode: Option<String>,
+    #[serde(rename(deserialize = "ca-file", serialize = "ca-file"))]
+    pub ca_file: Option<String>,
+    #[serde(rename(deserialize = "server-name", serialize = "server-name"))]
+    pub server_name: Option<String>,
+    #[serde(rename(deserialize = "fingerprint", serialize = "fingerprint"), deserialize_with = "deserialize_one_or_many")]
+    pub fingerprints: Option<Vec<String>>,
+    // Client certificate and private key, for servers requiring client certificates
+    #[serde(rename(deserialize = "client-cert", serialize = "client-cert"))]
+    pub client_cert: Option<String>,
+    #[serde(rename(deserialize = "client-key", serialize = "client-key"))]
+    pub client_key: Option<String>,
 }
 
-impl Default for ClientCertificate {
-    fn default() -> Self {
-        Self {
-            der_cert_pub: None,
-        }
+// Accepts either a single value or a list of values, i.e. fingerprint = "..." or fingerprint = ["...", "..."].
+fn deserialize_one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
     }
+
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(value)) => Some(vec![value]),
+        Some(OneOrMany::Many(values)) => Some(values),
+        None => None,
+    })
+}
+
+// Token sent with each request, as issued by the server when pairing.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ClientAuth {
+    pub token: Option<String>,
+}
+
+// Timeouts are in seconds, 0 disabling them. Failed attempts are retried up to "retries" times,
+// waiting retry-delay milliseconds before the first retry and twice as long before each next one.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ClientConnection {
+    #[serde(rename(deserialize = "connect-timeout", serialize = "connect-timeout"))]
+    pub connect_timeout: Option<u64>,
+    #[serde(rename(deserialize = "read-timeout", serialize = "read-timeout"))]
+    pub read_timeout: Option<u64>,
+    #[serde(rename(deserialize = "write-timeout", serialize = "write-timeout"))]
+    pub write_timeout: Option<u64>,
+    pub retries: Option<u32>,
+    #[serde(rename(deserialize = "retry-delay", serialize = "retry-delay"))]
+    pub retry_delay: Option<u64>,
+    // socks5:// or http:// URL, see the proxy module
+    pub proxy: Option<String>,
+    // Command connected to the server, i.e. "ssh jump nc %h %p", used instead of the proxy
+    #[serde(rename(deserialize = "proxy-command", serialize = "proxy-command"))]
+    pub proxy_command: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 #[serde(default)]
 pub struct ClientConfig {
     pub server: Server,
     pub certificate: ClientCertificate,
+    pub auth: ClientAuth,
+    pub tls: tls::TlsPolicy,
+    pub connection: ClientConnection,
 }
 
-impl Default for ClientConfig {
-    fn default() -> Self {
-        Self {
-            server: Server::default(),
-            certificate: ClientCertificate::default(),
+// Strips the optional brackets around an IPv6 literal, i.e. "[::1]" becomes "::1".
+pub fn unbracket_host(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+// Formats a host and port as a socket address, bracketing IPv6 literals.
+// Path addresses are returned as is, as they don't use a port.
+pub fn format_address(host: &str, port: u32) -> String {
+    let host = unbracket_host(host);
+
+    if is_path_address(host) {
+        host.to_string()
+    } else if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+// Returns the context identifier of "vsock:CID" hosts, or None for network hosts.
+// The CID can also be one of the "any", "local" or "host" aliases.
+pub fn parse_vsock_cid(host: &str) -> Result<Option<u32>, Box<dyn Error>> {
+    match host.strip_prefix(VSOCK_PREFIX) {
+        Some("any") => Ok(Some(VSOCK_CID_ANY)),
+        Some("local") => Ok(Some(VSOCK_CID_LOCAL)),
+        Some("host") => Ok(Some(VSOCK_CID_HOST)),
+        Some(cid) => cid
+            .parse::<u32>()
+            .map(Some)
+            .map_err(|e| format!("Invalid vsock context identifier in '{}': {}", host, e).into()),
+        None => Ok(None),
+    }
+}
+
+// Returns true for "unix:PATH" and "device:PATH" hosts, which are paths rather than network addresses.
+pub fn is_path_address(host: &str) -> bool {
+    host.starts_with(UNIX_PREFIX) || host.starts_with(DEVICE_PREFIX)
+}
+
+// Returns the port of a network host, as ports are 16-bit everywhere but on vsock.
+pub fn network_port(host: &str, port: u32) -> Result<u16, String> {
+    u16::try_from(port).map_err(|_| format!("Invalid port number {} for '{}', only vsock ports exceed 65535.", port, host))
+}
+
+// Parses "host", "host:port", "[ipv6]", "[ipv6]:port", "vsock:CID", "vsock:CID:PORT",
+// "unix:PATH" or "device:PATH" values. Ports are 16-bit, but 32-bit on vsock.
+// Bare IPv6 literals without brackets are accepted but cannot carry a port.
+pub fn parse_address(address: &str) -> Result<(String, Option<u32>), Box<dyn Error>> {
+    let parse_port = |port: &str| -> Result<u32, Box<dyn Error>> {
+        port.parse::<u16>()
+            .map(u32::from)
+            .map_err(|e| format!("Invalid port number in address '{}': {}", address, e).into())
+    };
+
+    if is_path_address(address) {
+        Ok((address.to_string(), None))
+    } else if let Some(rest) = address.strip_prefix(VSOCK_PREFIX) {
+        match rest.split_once(':') {
+            Some((cid, port)) => {
+                let port = port
+                    .parse::<u32>()
+                    .map_err(|e| format!("Invalid port number in address '{}': {}", address, e))?;
+
+                Ok((format!("{}{}", VSOCK_PREFIX, cid), Some(port)))
+            }
+            None => Ok((address.to_string(), None)),
         }
+    } else if let Some(rest) = address.strip_prefix('[') {
+        let (host, remainder) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("Missing closing bracket in address '{}'!", address))?;
+
+        if remainder.is_empty() {
+            Ok((host.to_string(), None))
+        } else if let Some(port) = remainder.strip_prefix(':') {
+            Ok((host.to_string(), Some(parse_port(port)?)))
+        } else {
+            Err(format!("Unexpected characters after ']' in address '{}'!", address).into())
+        }
+    } else if address.matches(':').count() > 1 {
+        Ok((address.to_string(), None))
+    } else if let Some((host, port)) = address.split_once(':') {
+        Ok((host.to_string(), Some(parse_port(port)?)))
+    } else {
+        Ok((address.to_string(), None))
     }
 }
 
@@ -98,7 +301,7 @@ pub fn resolve_default_cert_path(filename: &str) -> Option<String> {
             let pub_cert_file = data_dir_rclip_tcp.join(filename);
 
             if pub_cert_file.exists() {
-                println!("Found certificate data at: {}.", pub_cert_file.display());
+                eprintln!("Found certificate data at: {}.", pub_cert_file.display());
 
                 return Some(format!("{}", pub_cert_file.display()));
             }
@@ -108,6 +311,23 @@ pub fn resolve_default_cert_path(filename: &str) -> Option<String> {
     None
 }
 
+// Returns the data directory holding certificates, creating it if needed.
+pub fn create_data_dir() -> Result<PathBuf, Box<dyn Error>> {
+    if let Some(data_dir) = dirs::data_dir() {
+        let data_dir_rclip = data_dir.join(PROGRAM_GROUP);
+
+        if !data_dir_rclip.exists() {
+            if let Err(ex) = fs::create_dir_all(&data_dir_rclip) {
+                return Err(format!("Couldn't create data folder: {}. {}", data_dir_rclip.display(), ex).into())
+            }
+        }
+
+        Ok(data_dir_rclip)
+    } else {
+        Err("Cannot determine data directory on this machine!".into())
+    }
+}
+
 pub fn load_default_config <T> (filename: &str) -> Result<T, Box<dyn Error>> where T: Default + DeserializeOwned {
     if let Some(config_dir) = dirs::config_dir() {
         let config_dir_rclip_tcp = config_dir.join(PROGRAM_GROUP);
@@ -120,7 +340,8 @@ pub fn load_default_config <T> (filename: &str) -> Result<T, Box<dyn Error>> whe
                 let mut config_data = Vec::new();
                 file_config_client.read_to_end(&mut config_data)?;
                 let config_client: T = toml::from_slice(&config_data)?;
-                println!("Loaded configuration data from: {}.", config_client_file.display());
+                // Standard output is left to the command results, i.e. exported bundles
+                eprintln!("Loaded configuration data from: {}.", config_client_file.display());
 
                 return Ok(config_client);
             }
@@ -130,7 +351,7 @@ pub fn load_default_config <T> (filename: &str) -> Result<T, Box<dyn Error>> whe
     Ok(T::default())
 }
 
-// Only used in the GUI Desktop client
+// Used by the GUI Desktop client and when pairing with a server
 #[allow(dead_code)]
 pub fn save_config <T> (config_instance: T, filename: &str) -> Result<(), Box<dyn Error>> where T: Default + Serialize {
     if let Some(config_dir) = dirs::config_dir() {
@@ -138,16 +359,26 @@ pub fn save_config <T> (config_instance: T, filename: &str) -> Result<(), Box<dy
 
         if !cfg_dir.exists() {
             if let Err(ex) = fs::create_dir_all(&cfg_dir) {
-                return Err(format!("Couldn't create configuration folder: {}. {}", cfg_dir.display(), ex.to_string()).into())
+                return Err(format!("Couldn't create configuration folder: {}. {}", cfg_dir.display(), ex).into())
             }
         }
 
         let config_path = cfg_dir.join(filename);
-        let mut f = fs::OpenOptions::new().create(true).write(true).truncate(true).open(config_path.clone())?;        
+        let mut options = fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+
+        // The configuration may hold a client token
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut f = options.open(config_path.clone())?;
         let config_data = toml::to_vec(&config_instance)?;
 
         if let Err(e) = f.write(&config_data) {
-            Err(format!("Could not save configuration! {}", e.to_string()).into())
+            Err(format!("Could not save configuration! {}", e).into())
         } else {
             Ok(())
         }
@@ -155,3 +386,54 @@ pub fn save_config <T> (config_instance: T, filename: &str) -> Result<(), Box<dy
         Err("Cannot determine configuration directory on this machine!".into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(address: &str) -> (String, Option<u32>) {
+        parse_address(address).unwrap()
+    }
+
+    #[test]
+    fn parses_network_addresses() {
+        assert_eq!(address("localhost"), ("localhost".to_string(), None));
+        assert_eq!(address("127.0.0.1:10080"), ("127.0.0.1".to_string(), Some(10080)));
+        assert_eq!(address("[::1]"), ("::1".to_string(), None));
+        assert_eq!(address("[::1]:10080"), ("::1".to_string(), Some(10080)));
+        assert_eq!(address("fe80::1"), ("fe80::1".to_string(), None));
+
+        assert!(parse_address("localhost:http").is_err());
+        assert!(parse_address("localhost:65536").is_err());
+        assert!(parse_address("[::1").is_err());
+        assert!(parse_address("[::1]10080").is_err());
+    }
+
+    #[test]
+    fn parses_vsock_and_path_addresses() {
+        assert_eq!(address("vsock:3"), ("vsock:3".to_string(), None));
+        assert_eq!(address("vsock:host:10080"), ("vsock:host".to_string(), Some(10080)));
+        assert_eq!(address("vsock:3:4294967295"), ("vsock:3".to_string(), Some(u32::MAX)));
+        assert_eq!(address("unix:/run/rclip:1.sock"), ("unix:/run/rclip:1.sock".to_string(), None));
+        assert_eq!(address("device:/dev/vport0p1"), ("device:/dev/vport0p1".to_string(), None));
+
+        assert!(parse_address("vsock:3:port").is_err());
+        assert!(parse_address("vsock:3:4294967296").is_err());
+
+        assert_eq!(network_port("127.0.0.1", 65535), Ok(65535));
+        assert!(network_port("127.0.0.1", 65536).is_err());
+    }
+
+    #[test]
+    fn parses_vsock_context_identifiers() {
+        assert_eq!(parse_vsock_cid("vsock:3").unwrap(), Some(3));
+        assert_eq!(parse_vsock_cid("vsock:any").unwrap(), Some(VSOCK_CID_ANY));
+        assert_eq!(parse_vsock_cid("vsock:local").unwrap(), Some(VSOCK_CID_LOCAL));
+        assert_eq!(parse_vsock_cid("vsock:host").unwrap(), Some(VSOCK_CID_HOST));
+        assert_eq!(parse_vsock_cid("127.0.0.1").unwrap(), None);
+
+        assert!(parse_vsock_cid("vsock:").is_err());
+        assert!(parse_vsock_cid("vsock:-1").is_err());
+        assert!(parse_vsock_cid("vsock:guest").is_err());
+    }
+}
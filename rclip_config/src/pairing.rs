@@ -0,0 +1,162 @@
+// One-time pairing codes, as printed by `rclip-server pair` and typed into `rclip-client-cli pair`.
+// A code carries the beginning of the server certificate fingerprint, so that the client can
+// authenticate the server, and a random secret, so that the server can authenticate the client.
+
+// Long enough that no other certificate with the same prefix can be crafted while a code is valid,
+// while the secret only needs to resist a single guess. 15 bytes make 24 characters.
+pub const FINGERPRINT_PREFIX_LEN: usize = 10;
+pub const SECRET_LEN: usize = 5;
+
+// RFC 4648 base32 alphabet, whose digits only go from 2 to 7: 0, 1, 8 and 9 are left out, the first
+// three being easily confused with O, I and B
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const GROUP_LEN: usize = 4;
+
+#[derive(Clone)]
+pub struct PairingCode {
+    // Leading hex digits of the server certificate fingerprint, without the "sha256:" prefix
+    pub fingerprint_prefix: String,
+    pub secret: String,
+}
+
+impl PairingCode {
+    // Builds a code from a "sha256:..." fingerprint and a random secret.
+    pub fn new(fingerprint: &str, secret: &[u8]) -> Result<Self, String> {
+        let fingerprint = crate::certs::normalize_fingerprint(fingerprint)?;
+        let fingerprint_hex = &fingerprint[crate::certs::FINGERPRINT_PREFIX.len()..];
+
+        if secret.len() != SECRET_LEN {
+            return Err(format!("The pairing secret must be {} bytes long.", SECRET_LEN));
+        }
+
+        Ok(Self {
+            fingerprint_prefix: fingerprint_hex[..FINGERPRINT_PREFIX_LEN * 2].to_string(),
+            secret: to_hex(secret),
+        })
+    }
+
+    // Parses a code, ignoring case, dashes and spaces.
+    pub fn parse(code: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid pairing code '{}'.", code);
+        let mut bits: u64 = 0;
+        let mut bit_count = 0;
+        let mut bytes = Vec::with_capacity(FINGERPRINT_PREFIX_LEN + SECRET_LEN);
+
+        for c in code.chars().filter(|c| *c != '-' && !c.is_whitespace()) {
+            let c = c.to_ascii_uppercase() as u8;
+            let value = ALPHABET.iter().position(|a| *a == c).ok_or_else(invalid)?;
+
+            bits = (bits << 5) | value as u64;
+            bit_count += 5;
+
+            if bit_count >= 8 {
+                bit_count -= 8;
+                bytes.push((bits >> bit_count) as u8);
+                bits &= (1 << bit_count) - 1;
+            }
+        }
+
+        if bytes.len() != FINGERPRINT_PREFIX_LEN + SECRET_LEN || bit_count != 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            fingerprint_prefix: to_hex(&bytes[..FINGERPRINT_PREFIX_LEN]),
+            secret: to_hex(&bytes[FINGERPRINT_PREFIX_LEN..]),
+        })
+    }
+
+    // Whether a "sha256:..." fingerprint is the one of the server that printed the code.
+    pub fn matches_fingerprint(&self, fingerprint: &str) -> bool {
+        fingerprint
+            .strip_prefix(crate::certs::FINGERPRINT_PREFIX)
+            .map(|hex| hex.starts_with(&self.fingerprint_prefix))
+            .unwrap_or(false)
+    }
+}
+
+// Groups of four characters separated by dashes, i.e. ABCD-EFGH-IJKL-MNOP-QRST-UVWX.
+impl std::fmt::Display for PairingCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = from_hex(&self.fingerprint_prefix);
+        bytes.extend(from_hex(&self.secret));
+
+        let mut chars = Vec::with_capacity(bytes.len() * 8 / 5);
+        let mut bits: u64 = 0;
+        let mut bit_count = 0;
+
+        for byte in bytes {
+            bits = (bits << 8) | byte as u64;
+            bit_count += 8;
+
+            while bit_count >= 5 {
+                bit_count -= 5;
+                chars.push(ALPHABET[((bits >> bit_count) & 0x1f) as usize] as char);
+            }
+        }
+
+        let groups: Vec<String> = chars
+            .chunks(GROUP_LEN)
+            .map(|group| group.iter().collect())
+            .collect();
+
+        write!(f, "{}", groups.join("-"))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "sha256:11e653262d325306d06e878202f0f741b8de6611e8fe4207984cf2bea6e16f66";
+    const SECRET: [u8; SECRET_LEN] = [0xde, 0xad, 0xbe, 0xef, 0x42];
+
+    #[test]
+    fn round_trips_codes() {
+        let code = PairingCode::new(FINGERPRINT, &SECRET).unwrap();
+        let printed = code.to_string();
+
+        assert_eq!(printed.len(), 6 * GROUP_LEN + 5);
+        assert!(printed.split('-').all(|group| group.len() == GROUP_LEN));
+
+        let parsed = PairingCode::parse(&printed).unwrap();
+        assert_eq!(parsed.fingerprint_prefix, "11e653262d325306d06e");
+        assert_eq!(parsed.secret, "deadbeef42");
+        assert!(parsed.matches_fingerprint(FINGERPRINT));
+
+        // Typed in lower case, without dashes or with spaces instead
+        let typed = printed.to_lowercase().replace('-', "");
+        assert_eq!(PairingCode::parse(&typed).unwrap().secret, code.secret);
+        assert_eq!(PairingCode::parse(&printed.replace('-', " ")).unwrap().secret, code.secret);
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        let printed = PairingCode::new(FINGERPRINT, &SECRET).unwrap().to_string();
+
+        assert!(PairingCode::parse(&printed[..printed.len() - 1]).is_err());
+        assert!(PairingCode::parse(&format!("{}A", printed)).is_err());
+        assert!(PairingCode::parse(&printed.replacen(|c: char| c.is_ascii_alphabetic(), "8", 1)).is_err());
+
+        assert!(PairingCode::new(FINGERPRINT, &SECRET[1..]).is_err());
+        assert!(PairingCode::new("sha256:1234", &SECRET).is_err());
+    }
+
+    #[test]
+    fn matches_only_the_server_fingerprint() {
+        let code = PairingCode::new(FINGERPRINT, &SECRET).unwrap();
+
+        assert!(!code.matches_fingerprint(&FINGERPRINT.replace("11e6", "11e7")));
+        assert!(!code.matches_fingerprint(&FINGERPRINT["sha256:".len()..]));
+    }
+}
@@ -0,0 +1,65 @@
+// Percent escapes (RFC 3986) of connection bundle URIs and proxy URLs.
+
+// Escapes all but unreserved characters and ':', which separates the prefix of vsock, unix and device hosts.
+pub(crate) fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Invalid escapes are kept as is, and invalid UTF-8 is replaced.
+pub(crate) fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' {
+            bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+
+        match escaped {
+            Some(byte) => {
+                ret.push(byte);
+                i += 3;
+            }
+            None => {
+                ret.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&ret).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_reserved_characters() {
+        assert_eq!(encode("vsock:host"), "vsock:host");
+        assert_eq!(encode("unix:/run/rclip 1.sock"), "unix:%2Frun%2Frclip%201.sock");
+        assert_eq!(encode("a?b&c%é"), "a%3Fb%26c%25%C3%A9");
+        assert_eq!(decode(&encode("a?b&c%é")), "a?b&c%é");
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(decode("%C3%A9"), "é");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz%4"), "%zz%4");
+        assert_eq!(decode("%FF"), "\u{fffd}");
+    }
+}
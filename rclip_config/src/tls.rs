@@ -0,0 +1,174 @@
+// TLS policy of the [tls] configuration section, shared by the client and the server: protocol versions,
+// cipher suites and key exchange groups, all defaulting to the rustls safe defaults. Names are matched
+// without regard to case, i.e. versions = ["1.3"] and cipher-suites = ["TLS13_AES_256_GCM_SHA384"].
+
+use rustls::{
+    ConfigBuilder, ConfigSide, SupportedCipherSuite, SupportedKxGroup, SupportedProtocolVersion, WantsCipherSuites,
+    WantsVerifier,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct TlsPolicy {
+    pub versions: Option<Vec<String>>,
+    // In order of preference
+    #[serde(rename(deserialize = "cipher-suites", serialize = "cipher-suites"))]
+    pub cipher_suites: Option<Vec<String>>,
+    #[serde(rename(deserialize = "kx-groups", serialize = "kx-groups"))]
+    pub kx_groups: Option<Vec<String>>,
+}
+
+// Applies the policy to a client or server configuration builder.
+pub fn apply<S: ConfigSide>(
+    builder: ConfigBuilder<S, WantsCipherSuites>,
+    policy: &TlsPolicy,
+) -> Result<ConfigBuilder<S, WantsVerifier>, String> {
+    let cipher_suites = cipher_suites(policy)?;
+    let kx_groups = kx_groups(policy)?;
+    let mut versions = versions(policy)?;
+
+    // Without configured versions, those of the cipher suites are enabled, i.e. TLS 1.3 for TLS13_* suites only
+    if policy.versions.is_none() {
+        versions.retain(|version| cipher_suites.iter().any(|suite| suite.version() == *version));
+    }
+
+    // Each enabled version needs at least one cipher suite, otherwise handshakes would fail later on
+    for version in &versions {
+        if !cipher_suites.iter().any(|suite| suite.version() == *version) {
+            return Err(format!(
+                "Unsupported TLS policy: TLS {} is enabled, but none of the cipher suites is usable with it.",
+                version_number(version)
+            ));
+        }
+    }
+
+    // The default cipher suites cover all versions, only the configured ones must all be usable
+    if policy.cipher_suites.is_some() {
+        for suite in &cipher_suites {
+            if !versions.contains(&suite.version()) {
+                return Err(format!(
+                    "Unsupported TLS policy: the cipher suite {:?} requires TLS {}, which isn't enabled.",
+                    suite.suite(),
+                    version_number(suite.version())
+                ));
+            }
+        }
+    }
+
+    builder
+        .with_cipher_suites(&cipher_suites)
+        .with_kx_groups(&kx_groups)
+        .with_protocol_versions(&versions)
+        .map_err(|e| format!("Unsupported TLS policy. {}", e))
+}
+
+fn versions(policy: &TlsPolicy) -> Result<Vec<&'static SupportedProtocolVersion>, String> {
+    let names = match &policy.versions {
+        Some(names) => names,
+        None => return Ok(rustls::DEFAULT_VERSIONS.to_vec()),
+    };
+
+    let mut ret = Vec::with_capacity(names.len());
+
+    for name in names {
+        // "1.3", "TLS 1.3" or "TLSv1.3"
+        let lowercase_name = name.to_ascii_lowercase();
+        let number = lowercase_name.trim_start_matches("tls").trim_start_matches('v').trim();
+
+        let version = rustls::ALL_VERSIONS
+            .iter()
+            .find(|version| version_number(version) == number)
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported TLS version '{}', expecting one of: {}.",
+                    name,
+                    rustls::ALL_VERSIONS.iter().map(|v| version_number(v)).collect::<Vec<_>>().join(", ")
+                )
+            })?;
+
+        ret.push(*version);
+    }
+
+    if ret.is_empty() {
+        return Err("Unsupported TLS policy: no TLS version is enabled.".to_string());
+    }
+
+    Ok(ret)
+}
+
+fn cipher_suites(policy: &TlsPolicy) -> Result<Vec<SupportedCipherSuite>, String> {
+    let names = match &policy.cipher_suites {
+        Some(names) => names,
+        None => return Ok(rustls::DEFAULT_CIPHER_SUITES.to_vec()),
+    };
+
+    let mut ret = Vec::with_capacity(names.len());
+
+    for name in names {
+        let suite = rustls::ALL_CIPHER_SUITES
+            .iter()
+            .find(|suite| format!("{:?}", suite.suite()).eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported cipher suite '{}', expecting one of: {}.",
+                    name,
+                    rustls::ALL_CIPHER_SUITES
+                        .iter()
+                        .map(|suite| format!("{:?}", suite.suite()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+
+        ret.push(*suite);
+    }
+
+    if ret.is_empty() {
+        return Err("Unsupported TLS policy: no cipher suite is enabled.".to_string());
+    }
+
+    Ok(ret)
+}
+
+fn kx_groups(policy: &TlsPolicy) -> Result<Vec<&'static SupportedKxGroup>, String> {
+    let names = match &policy.kx_groups {
+        Some(names) => names,
+        None => return Ok(rustls::ALL_KX_GROUPS.to_vec()),
+    };
+
+    let mut ret = Vec::with_capacity(names.len());
+
+    for name in names {
+        let group = rustls::ALL_KX_GROUPS
+            .iter()
+            .find(|group| format!("{:?}", group.name).eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported key exchange group '{}', expecting one of: {}.",
+                    name,
+                    rustls::ALL_KX_GROUPS
+                        .iter()
+                        .map(|group| format!("{:?}", group.name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+
+        ret.push(*group);
+    }
+
+    if ret.is_empty() {
+        return Err("Unsupported TLS policy: no key exchange group is enabled.".to_string());
+    }
+
+    Ok(ret)
+}
+
+fn version_number(version: &SupportedProtocolVersion) -> &'static str {
+    if *version == rustls::version::TLS13 {
+        "1.3"
+    } else {
+        "1.2"
+    }
+}
@@ -0,0 +1,259 @@
+// Wire format shared by the server and the clients, one request and one response per session.
+//
+// Requests are an optional "TOKEN:<token>" line followed by a command, i.e. "WRITE:<text>".
+// Responses are "SUCCESS:<body>" or "ERROR:<code>:<message>", all being written out with Display.
+//
+// Transports split requests anywhere (TLS records, pipes of inetd or SSH), so that requests are sent
+// after a "LENGTH:<bytes>" header line. Responses end with the session.
+
+use std::error::Error;
+use std::fmt;
+
+pub const CMD_TOKEN: &str  = "TOKEN:";
+pub const CMD_READ: &str   = "READ:";
+pub const CMD_WRITE: &str  = "WRITE:";
+pub const CMD_CLEAR: &str  = "CLEAR:";
+pub const CMD_STATUS: &str = "STATUS:";
+pub const CMD_PAIR: &str   = "PAIR:";
+
+pub const HEADER_LENGTH: &str = "LENGTH:";
+// Longest header line, line break included
+pub const MAX_HEADER_LEN: usize = 32;
+
+pub const RESPONSE_SUCCESS: &str = "SUCCESS:";
+pub const RESPONSE_ERROR: &str   = "ERROR:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Read,
+    Write(String),
+    Clear,
+    Status,
+    // Secret part of the pairing code, and name of the client on the server
+    Pair { secret: String, client_name: String },
+}
+
+impl Command {
+    // Anything following the READ, CLEAR and STATUS commands is ignored.
+    pub fn decode(data: &str) -> Result<Self, DecodeError> {
+        if let Some(text) = data.strip_prefix(CMD_WRITE) {
+            Ok(Command::Write(text.to_string()))
+        } else if let Some(pairing_request) = data.strip_prefix(CMD_PAIR) {
+            let pairing_request = pairing_request.trim();
+            let (secret, client_name) = pairing_request.split_once(' ').unwrap_or((pairing_request, ""));
+
+            Ok(Command::Pair {
+                secret: secret.to_string(),
+                client_name: client_name.trim().to_string(),
+            })
+        } else if data.starts_with(CMD_READ) {
+            Ok(Command::Read)
+        } else if data.starts_with(CMD_CLEAR) {
+            Ok(Command::Clear)
+        } else if data.starts_with(CMD_STATUS) {
+            Ok(Command::Status)
+        } else {
+            Err(DecodeError::UnknownCommand(data.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Read => write!(f, "{}", CMD_READ),
+            Command::Write(text) => write!(f, "{}{}", CMD_WRITE, text),
+            Command::Clear => write!(f, "{}", CMD_CLEAR),
+            Command::Status => write!(f, "{}", CMD_STATUS),
+            Command::Pair { secret, client_name } if client_name.is_empty() => write!(f, "{}{}", CMD_PAIR, secret),
+            Command::Pair { secret, client_name } => write!(f, "{}{} {}", CMD_PAIR, secret, client_name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub token: Option<String>,
+    pub command: Command,
+}
+
+impl Request {
+    pub fn new(command: Command, token: Option<String>) -> Self {
+        Self { token, command }
+    }
+
+    pub fn decode(request: &str) -> Result<Self, DecodeError> {
+        let (token, data) = split_token(request);
+
+        Ok(Self {
+            token: token.map(|token| token.to_string()),
+            command: Command::decode(data)?,
+        })
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.token {
+            Some(token) => write!(f, "{}{}\n{}", CMD_TOKEN, token, self.command),
+            None => write!(f, "{}", self.command),
+        }
+    }
+}
+
+// Request with its length header, as written to the server.
+pub fn frame_request(request: &str) -> String {
+    format!("{}{}\n{}", HEADER_LENGTH, request.len(), request)
+}
+
+// Returns the length in bytes of the request following a header line, line break included.
+pub fn decode_length_header(header: &[u8]) -> Result<usize, DecodeError> {
+    let invalid = || DecodeError::InvalidHeader(String::from_utf8_lossy(header).trim_end().to_string());
+
+    std::str::from_utf8(header)
+        .ok()
+        .and_then(|header| header.strip_suffix('\n'))
+        .and_then(|header| header.strip_prefix(HEADER_LENGTH))
+        .and_then(|len| len.trim_end_matches('\r').parse::<usize>().ok())
+        .ok_or_else(invalid)
+}
+
+// Splits the optional token line from the command itself.
+pub fn split_token(request: &str) -> (Option<&str>, &str) {
+    match request.strip_prefix(CMD_TOKEN).and_then(|rest| rest.split_once('\n')) {
+        Some((token, rest)) => (Some(token.trim()), rest),
+        None => (None, request),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    // Clipboard text (empty to acknowledge writes), or "key=value" lines for the STATUS and PAIR commands
+    Success(String),
+    Error(ErrorResponse),
+}
+
+impl Response {
+    pub fn decode(response: &str) -> Result<Self, DecodeError> {
+        if let Some(body) = response.strip_prefix(RESPONSE_SUCCESS) {
+            Ok(Response::Success(body.to_string()))
+        } else if let Some(error) = response.strip_prefix(RESPONSE_ERROR) {
+            Ok(Response::Error(ErrorResponse::decode(error)))
+        } else {
+            Err(DecodeError::InvalidResponse(response.to_string()))
+        }
+    }
+
+    pub fn into_result(self) -> Result<String, ErrorResponse> {
+        match self {
+            Response::Success(body) => Ok(body),
+            Response::Error(error) => Err(error),
+        }
+    }
+}
+
+impl From<Result<String, ErrorResponse>> for Response {
+    fn from(ret: Result<String, ErrorResponse>) -> Self {
+        match ret {
+            Ok(body) => Response::Success(body),
+            Err(error) => Response::Error(error),
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Success(body) => write!(f, "{}{}", RESPONSE_SUCCESS, body),
+            Response::Error(error) => write!(f, "{}{}:{}", RESPONSE_ERROR, error.code, error.message),
+        }
+    }
+}
+
+// Classes of failures reported by the server, for clients to tell them apart without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    // Missing or unknown client token, failed pairing
+    Auth,
+    // Request larger than accepted by the server
+    Limit,
+    // Malformed or unknown request, or command unavailable on this connection
+    Protocol,
+    // Failure of the server itself, i.e. of its clipboard storage
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Auth => "auth",
+            ErrorCode::Limit => "limit",
+            ErrorCode::Protocol => "protocol",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Self> {
+        [ErrorCode::Auth, ErrorCode::Limit, ErrorCode::Protocol, ErrorCode::Internal]
+            .into_iter()
+            .find(|error_code| error_code.as_str() == code)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    // Errors without a known code, i.e. from servers predating error codes, are internal ones.
+    fn decode(error: &str) -> Self {
+        match error.split_once(':').and_then(|(code, message)| Some((ErrorCode::parse(code)?, message))) {
+            Some((code, message)) => Self::new(code, message),
+            None => Self::new(ErrorCode::Internal, error),
+        }
+    }
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ErrorResponse {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    UnknownCommand(String),
+    InvalidHeader(String),
+    InvalidResponse(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownCommand(data) => write!(f, "Unknown message {}", data),
+            DecodeError::InvalidHeader(header) => {
+                write!(f, "Invalid request header '{}', expecting '{}<bytes>'", header, HEADER_LENGTH)
+            }
+            DecodeError::InvalidResponse(response) => write!(f, "Unexpected response from the server: {}", response),
+        }
+    }
+}
+
+impl Error for DecodeError {}
@@ -0,0 +1,121 @@
+use rclip_protocol::{Command, DecodeError, ErrorCode, ErrorResponse, Request, Response};
+
+fn round_trip(request: Request, encoded: &str) {
+    assert_eq!(request.to_string(), encoded);
+    assert_eq!(Request::decode(encoded), Ok(request));
+}
+
+#[test]
+fn commands() {
+    round_trip(Request::new(Command::Read, None), "READ:");
+    round_trip(Request::new(Command::Clear, None), "CLEAR:");
+    round_trip(Request::new(Command::Status, None), "STATUS:");
+    round_trip(Request::new(Command::Write("some text".to_string()), None), "WRITE:some text");
+}
+
+#[test]
+fn write_keeps_text_unchanged() {
+    let text = " multi-line\ntext with WRITE: and SUCCESS: markers\r\n\u{e9}\u{1f4cb} ";
+    round_trip(Request::new(Command::Write(text.to_string()), None), &format!("WRITE:{}", text));
+    round_trip(Request::new(Command::Write(String::new()), None), "WRITE:");
+}
+
+#[test]
+fn token_line() {
+    let token = Some("0123abcd".to_string());
+
+    round_trip(Request::new(Command::Read, token.clone()), "TOKEN:0123abcd\nREAD:");
+    round_trip(Request::new(Command::Write("a\nb".to_string()), token), "TOKEN:0123abcd\nWRITE:a\nb");
+}
+
+#[test]
+fn pairing() {
+    let pair = |secret: &str, client_name: &str| Command::Pair {
+        secret: secret.to_string(),
+        client_name: client_name.to_string(),
+    };
+
+    round_trip(Request::new(pair("ABCDEFGH", "laptop"), None), "PAIR:ABCDEFGH laptop");
+    round_trip(Request::new(pair("ABCDEFGH", "my laptop"), None), "PAIR:ABCDEFGH my laptop");
+    round_trip(Request::new(pair("ABCDEFGH", ""), None), "PAIR:ABCDEFGH");
+    assert_eq!(Command::decode("PAIR: ABCDEFGH  laptop \n"), Ok(pair("ABCDEFGH", "laptop")));
+}
+
+#[test]
+fn lenient_decoding() {
+    assert_eq!(Command::decode("READ:ignored"), Ok(Command::Read));
+    assert_eq!(Command::decode("CLEAR:"), Ok(Command::Clear));
+    assert_eq!(Request::decode("TOKEN: 0123abcd \nSTATUS:"), Ok(Request::new(Command::Status, Some("0123abcd".to_string()))));
+
+    // Without a line break, the token prefix is taken for the command
+    assert_eq!(Request::decode("TOKEN:0123abcd"), Err(DecodeError::UnknownCommand("TOKEN:0123abcd".to_string())));
+}
+
+#[test]
+fn unknown_commands() {
+    assert_eq!(Command::decode("read:"), Err(DecodeError::UnknownCommand("read:".to_string())));
+    assert_eq!(Command::decode(""), Err(DecodeError::UnknownCommand(String::new())));
+    assert_eq!(DecodeError::UnknownCommand("DELETE:".to_string()).to_string(), "Unknown message DELETE:");
+}
+
+#[test]
+fn responses() {
+    let cases = [
+        (Response::Success(String::new()), "SUCCESS:"),
+        (Response::Success("line 1\nline 2".to_string()), "SUCCESS:line 1\nline 2"),
+        (Response::Success("version=1.0.3\nfingerprint=sha256:00".to_string()), "SUCCESS:version=1.0.3\nfingerprint=sha256:00"),
+    ];
+
+    for (response, encoded) in cases {
+        assert_eq!(response.to_string(), encoded);
+        assert_eq!(Response::decode(encoded), Ok(response));
+    }
+
+    assert!(matches!(Response::decode("HTTP/1.1 400"), Err(DecodeError::InvalidResponse(_))));
+}
+
+#[test]
+fn error_codes() {
+    let cases = [
+        (ErrorCode::Auth, "ERROR:auth:No pairing in progress."),
+        (ErrorCode::Limit, "ERROR:limit:The request exceeds 10 bytes."),
+        (ErrorCode::Protocol, "ERROR:protocol:Unknown message HELLO"),
+        (ErrorCode::Internal, "ERROR:internal:Could not read 'clipboard': denied"),
+    ];
+
+    for (code, encoded) in cases {
+        let message = encoded.splitn(3, ':').last().unwrap_or_default();
+        let response = Response::from(Err(ErrorResponse::new(code, message)));
+
+        assert_eq!(response.to_string(), encoded);
+        assert_eq!(Response::decode(encoded), Ok(response));
+        assert_eq!(ErrorCode::parse(code.as_str()), Some(code));
+    }
+}
+
+#[test]
+fn errors_without_code() {
+    assert_eq!(
+        Response::decode("ERROR:Unknown client token: abc").map(|response| response.into_result()),
+        Ok(Err(ErrorResponse::new(ErrorCode::Internal, "Unknown client token: abc")))
+    );
+    assert_eq!(Response::decode("ERROR:"), Ok(Response::Error(ErrorResponse::new(ErrorCode::Internal, ""))));
+}
+
+#[test]
+fn length_headers() {
+    let request = Request::new(Command::Write("\u{e9}t\u{e9}".to_string()), None).to_string();
+    let framed = rclip_protocol::frame_request(&request);
+    assert_eq!(framed, "LENGTH:11\nWRITE:\u{e9}t\u{e9}");
+
+    let (header, rest) = framed.split_at(framed.find('\n').unwrap() + 1);
+    assert_eq!(rclip_protocol::decode_length_header(header.as_bytes()), Ok(rest.len()));
+    assert_eq!(rclip_protocol::decode_length_header(b"LENGTH:0\r\n"), Ok(0));
+
+    for header in [&b"LENGTH:12"[..], b"LENGTH:\n", b"LENGTH:-1\n", b"LENGTH: 1\n", b"WRITE:text\n", b"LENGTH:\xff\n"] {
+        assert!(matches!(rclip_protocol::decode_length_header(header), Err(DecodeError::InvalidHeader(_))));
+    }
+
+    let err = rclip_protocol::decode_length_header(b"READ:\n").unwrap_err();
+    assert_eq!(err.to_string(), "Invalid request header 'READ:', expecting 'LENGTH:<bytes>'");
+}
@@ -0,0 +1,68 @@
+// Client tokens, issued when pairing and then sent in front of each request as a "TOKEN:<token>" line.
+// Only their SHA-256 digest is kept, one "<digest> <label>" line per client in the rclip data directory.
+
+use rand::RngCore;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const FILENAME_CLIENT_TOKENS: &str = "client_tokens";
+
+const TOKEN_LEN: usize = 32;
+
+// Generates a token and records its digest, the token itself is only returned to the client.
+pub fn issue_token(label: &str) -> Result<String, String> {
+    let mut bytes = [0u8; TOKEN_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let path = tokens_path()?;
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut f = options
+        .open(&path)
+        .map_err(|e| format!("Could not open '{}'. {}", path.display(), e))?;
+
+    writeln!(f, "{} {}", digest(&token), label)
+        .map_err(|e| format!("Could not write '{}'. {}", path.display(), e))?;
+
+    Ok(token)
+}
+
+// The file is read again for each request, so that removing a line revokes a client right away.
+pub fn is_known_token(token: &str) -> Result<bool, String> {
+    let path = tokens_path()?;
+
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read '{}'. {}", path.display(), e))?;
+    let token_digest = digest(token);
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .any(|known_digest| known_digest == token_digest))
+}
+
+fn tokens_path() -> Result<PathBuf, String> {
+    let data_dir = rclip_config::create_data_dir().map_err(|e| e.to_string())?;
+
+    Ok(data_dir.join(FILENAME_CLIENT_TOKENS))
+}
+
+fn digest(token: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, token.as_bytes());
+    let hex: String = digest.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+
+    format!("sha256:{}", hex)
+}
@@ -0,0 +1,554 @@
+// Built-in certificate authority for client certificates (`ca` subcommand): `ca init` creates the CA,
+// `ca issue <device-name>` a client certificate with the device name as subject, and `ca revoke <device-name>`
+// adds the certificates of a device to the revocation list, which is checked during each handshake.
+// Everything lives in the "ca" folder of the data directory, and issued certificates are listed in
+// its "issued" file, one "<fingerprint> <device-name>" line per certificate.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose,
+};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use time::{Duration, OffsetDateTime};
+use tokio_rustls::rustls::server::{AllowAnyAuthenticatedClient, ClientCertVerified, ClientCertVerifier};
+use tokio_rustls::rustls::{self, Certificate as TlsCertificate, DistinguishedNames, RootCertStore};
+
+pub const SUBCOMMAND_CA: &str = "ca";
+
+const SUBCOMMAND_INIT: &str   = "init";
+const SUBCOMMAND_ISSUE: &str  = "issue";
+const SUBCOMMAND_REVOKE: &str = "revoke";
+const SUBCOMMAND_LIST: &str   = "list";
+
+const DIRNAME_CA: &str       = "ca";
+const FILENAME_CA_CERT: &str = "ca-cert.der";
+const FILENAME_CA_KEY: &str  = "ca-key.der";
+const FILENAME_ISSUED: &str  = "issued";
+const FILENAME_REVOKED: &str = "revoked";
+
+const DEFAULT_CA_NAME: &str              = "rclip CA";
+const DEFAULT_CA_VALIDITY_DAYS: &str     = "3650";
+const DEFAULT_CLIENT_VALIDITY_DAYS: &str = "365";
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name(SUBCOMMAND_CA)
+        .about("Manages the certificate authority of client certificates")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name(SUBCOMMAND_INIT)
+                .about("Creates the certificate authority")
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .help("Common name of the certificate authority")
+                        .required(false)
+                        .default_value(DEFAULT_CA_NAME)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("days")
+                        .long("days")
+                        .help("Validity period in days")
+                        .required(false)
+                        .default_value(DEFAULT_CA_VALIDITY_DAYS)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Replace an existing certificate authority, invalidating the certificates it issued")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(SUBCOMMAND_ISSUE)
+                .about("Issues a client certificate for a device")
+                .arg(
+                    Arg::with_name("device-name")
+                        .help("Name of the device, used as the certificate subject")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("days")
+                        .long("days")
+                        .help("Validity period in days")
+                        .required(false)
+                        .default_value(DEFAULT_CLIENT_VALIDITY_DAYS)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output-dir")
+                        .long("output-dir")
+                        .help("Destination folder [default: ca folder of the rclip data directory]")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Issue another certificate for a device which already has a valid one")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(SUBCOMMAND_REVOKE)
+                .about("Revokes the client certificates of a device")
+                .arg(
+                    Arg::with_name("device-name")
+                        .help("Name of the device")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(SubCommand::with_name(SUBCOMMAND_LIST).about("Lists the issued client certificates"))
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match matches.subcommand() {
+        (SUBCOMMAND_INIT, Some(init_matches)) => init(init_matches),
+        (SUBCOMMAND_ISSUE, Some(issue_matches)) => issue(issue_matches),
+        (SUBCOMMAND_REVOKE, Some(revoke_matches)) => revoke(revoke_matches),
+        (SUBCOMMAND_LIST, Some(_)) => list(),
+        _ => Err("Please provide a ca subcommand: init, issue, revoke or list.".into()),
+    }
+}
+
+// Location of the CA certificate trusted for client certificates, unless configured otherwise.
+pub fn default_ca_cert_path() -> Result<PathBuf, String> {
+    Ok(ca_dir()?.join(FILENAME_CA_CERT))
+}
+
+fn init(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let name = matches.value_of("name").unwrap_or(DEFAULT_CA_NAME);
+    let days = parse_days(matches)?;
+    let (cert_path, key_path) = create_ca(&ca_dir()?, name, days, matches.is_present("force"))?;
+
+    println!("Created certificate authority '{}'.", name);
+    println!("CA certificate: {}", cert_path.display());
+    println!("CA private key: {}", key_path.display());
+
+    Ok(())
+}
+
+// Returns the paths of the CA certificate and private key.
+fn create_ca(ca_dir: &Path, name: &str, days: u32, force: bool) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
+    let cert_path = ca_dir.join(FILENAME_CA_CERT);
+    let key_path = ca_dir.join(FILENAME_CA_KEY);
+
+    if !force && (cert_path.exists() || key_path.exists()) {
+        return Err(format!(
+            "A certificate authority already exists in '{}', use --force to replace it.",
+            ca_dir.display()
+        )
+        .into());
+    }
+
+    let mut params = CertificateParams::default();
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, name);
+    set_validity(&mut params, days);
+
+    let cert = Certificate::from_params(params)?;
+    let cert_der = cert.serialize_der()?;
+
+    crate::certgen::write_file(&cert_path, &cert_der, false)?;
+    crate::certgen::write_file(&key_path, &cert.serialize_private_key_der(), true)?;
+
+    // Certificates of a previous CA are no longer trusted anyway
+    for filename in [FILENAME_ISSUED, FILENAME_REVOKED] {
+        let path = ca_dir.join(filename);
+
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| format!("Could not remove '{}'. {}", path.display(), e))?;
+        }
+    }
+
+    Ok((cert_path, key_path))
+}
+
+fn issue(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let device_name = device_name(matches)?;
+    let days = parse_days(matches)?;
+    let ca_dir = ca_dir()?;
+
+    let output_dir = match matches.value_of("output-dir") {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| format!("Couldn't create output folder: {}. {}", dir, e))?;
+            PathBuf::from(dir)
+        }
+        None => ca_dir.clone(),
+    };
+
+    let issued = issue_certificate(&ca_dir, device_name, days, &output_dir, matches.is_present("force"))?;
+
+    println!("Issued client certificate for '{}'.", device_name);
+    println!("Client certificate: {}", issued.cert_path.display());
+    println!("Fingerprint: {}", issued.fingerprint);
+    println!("Client private key: {}", issued.key_path.display());
+
+    Ok(())
+}
+
+struct IssuedCertificate {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    fingerprint: String,
+}
+
+fn issue_certificate(
+    ca_dir: &Path,
+    device_name: &str,
+    days: u32,
+    output_dir: &Path,
+    force: bool,
+) -> Result<IssuedCertificate, Box<dyn Error>> {
+    let ca = load_ca(ca_dir)?;
+
+    if !force {
+        let revoked = read_entries(&ca_dir.join(FILENAME_REVOKED))?;
+        let active = read_entries(&ca_dir.join(FILENAME_ISSUED))?
+            .into_iter()
+            .any(|(fingerprint, name)| name == device_name && !revoked.iter().any(|(f, _)| *f == fingerprint));
+
+        if active {
+            return Err(format!(
+                "A certificate was already issued for '{}', revoke it first or use --force.",
+                device_name
+            )
+            .into());
+        }
+    }
+
+    let mut params = CertificateParams::default();
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.serial_number = Some(rand::random::<u64>() >> 1);
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, device_name);
+    set_validity(&mut params, days);
+
+    let cert = Certificate::from_params(params)?;
+    let cert_der = cert.serialize_der_with_signer(&ca)?;
+    let fingerprint = rclip_config::certs::fingerprint(&cert_der);
+
+    let cert_path = output_dir.join(format!("{}-cert.der", device_name));
+    let key_path = output_dir.join(format!("{}-key.der", device_name));
+
+    crate::certgen::write_file(&cert_path, &cert_der, false)?;
+    crate::certgen::write_file(&key_path, &cert.serialize_private_key_der(), true)?;
+    append_entry(&ca_dir.join(FILENAME_ISSUED), &fingerprint, device_name)?;
+
+    Ok(IssuedCertificate {
+        cert_path,
+        key_path,
+        fingerprint,
+    })
+}
+
+fn revoke(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let device_name = device_name(matches)?;
+    let fingerprints = revoke_device(&ca_dir()?, device_name)?;
+
+    for fingerprint in &fingerprints {
+        println!("Revoked certificate {}.", fingerprint);
+    }
+
+    println!(
+        "Revoked {} certificate(s) of '{}', the running server rejects them right away.",
+        fingerprints.len(),
+        device_name
+    );
+
+    Ok(())
+}
+
+// Returns the fingerprints of the newly revoked certificates.
+fn revoke_device(ca_dir: &Path, device_name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let revoked_path = ca_dir.join(FILENAME_REVOKED);
+    let revoked = read_entries(&revoked_path)?;
+    let mut ret = Vec::new();
+
+    for (fingerprint, name) in read_entries(&ca_dir.join(FILENAME_ISSUED))? {
+        if name == device_name && !revoked.iter().any(|(f, _)| *f == fingerprint) {
+            append_entry(&revoked_path, &fingerprint, &name)?;
+            ret.push(fingerprint);
+        }
+    }
+
+    if ret.is_empty() {
+        return Err(format!("No valid certificate was issued for '{}'.", device_name).into());
+    }
+
+    Ok(ret)
+}
+
+fn list() -> Result<(), Box<dyn Error>> {
+    let ca_dir = ca_dir()?;
+    let revoked = read_entries(&ca_dir.join(FILENAME_REVOKED))?;
+
+    for (fingerprint, name) in read_entries(&ca_dir.join(FILENAME_ISSUED))? {
+        let state = if revoked.iter().any(|(f, _)| *f == fingerprint) { "revoked" } else { "valid" };
+
+        println!("{} {} {}", name, fingerprint, state);
+    }
+
+    Ok(())
+}
+
+// Device names end up in file names and in whitespace separated lists.
+fn device_name<'a>(matches: &'a ArgMatches) -> Result<&'a str, Box<dyn Error>> {
+    let device_name = matches.value_of("device-name").unwrap_or_default();
+    let valid = !device_name.is_empty()
+        && !device_name.starts_with('.')
+        && device_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if !valid {
+        return Err(format!(
+            "Invalid device name '{}', only letters, digits, '-', '_' and '.' are allowed.",
+            device_name
+        )
+        .into());
+    }
+
+    Ok(device_name)
+}
+
+fn parse_days(matches: &ArgMatches) -> Result<u32, Box<dyn Error>> {
+    let days = matches
+        .value_of("days")
+        .unwrap_or(DEFAULT_CLIENT_VALIDITY_DAYS)
+        .parse::<u32>()
+        .map_err(|e| format!("Invalid number of days. {}", e))?;
+
+    if days == 0 {
+        return Err("The validity period must be at least one day.".into());
+    }
+
+    Ok(days)
+}
+
+fn set_validity(params: &mut CertificateParams, days: u32) {
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + Duration::days(days.into());
+}
+
+fn load_ca(ca_dir: &Path) -> Result<Certificate, Box<dyn Error>> {
+    let cert_path = ca_dir.join(FILENAME_CA_CERT);
+    let key_path = ca_dir.join(FILENAME_CA_KEY);
+
+    if !cert_path.exists() || !key_path.exists() {
+        return Err(format!(
+            "No certificate authority found in '{}', please run 'rclip-server ca init' first.",
+            ca_dir.display()
+        )
+        .into());
+    }
+
+    let cert_der = rclip_config::certs::load_certificates(&cert_path.display().to_string())?.remove(0);
+    let key_der = rclip_config::certs::load_private_key(&key_path.display().to_string())?;
+    let params = CertificateParams::from_ca_cert_der(&cert_der, KeyPair::from_der(&key_der)?)?;
+
+    Ok(Certificate::from_params(params)?)
+}
+
+fn ca_dir() -> Result<PathBuf, String> {
+    let ca_dir = rclip_config::create_data_dir().map_err(|e| e.to_string())?.join(DIRNAME_CA);
+
+    fs::create_dir_all(&ca_dir).map_err(|e| format!("Couldn't create CA folder: {}. {}", ca_dir.display(), e))?;
+
+    Ok(ca_dir)
+}
+
+fn read_entries(path: &Path) -> Result<Vec<(String, String)>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read '{}'. {}", path.display(), e))?;
+
+    Ok(contents
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(fingerprint, name)| (fingerprint.to_string(), name.trim().to_string()))
+        .collect())
+}
+
+fn append_entry(path: &Path, fingerprint: &str, device_name: &str) -> Result<(), String> {
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Could not open '{}'. {}", path.display(), e))?;
+
+    writeln!(f, "{} {}", fingerprint, device_name).map_err(|e| format!("Could not write '{}'. {}", path.display(), e))
+}
+
+// Requires client certificates issued by the CA, which are not revoked. The revocation list
+// is read again for each handshake, so that revoking a device takes effect right away.
+// Only CAs created by `ca init` have one, certificates of other CAs are never revoked.
+struct RevocationCheckingVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    revoked_path: Option<PathBuf>,
+}
+
+impl ClientCertVerifier for RevocationCheckingVerifier {
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &TlsCertificate,
+        intermediates: &[TlsCertificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self.inner.verify_client_cert(end_entity, intermediates, now)?;
+
+        let revoked_path = match &self.revoked_path {
+            Some(revoked_path) => revoked_path,
+            None => return Ok(verified),
+        };
+
+        let fingerprint = rclip_config::certs::fingerprint(&end_entity.0);
+        let revoked = read_entries(revoked_path).map_err(rustls::Error::General)?;
+
+        if let Some((_, device_name)) = revoked.iter().find(|(f, _)| *f == fingerprint) {
+            return Err(rustls::Error::General(format!(
+                "The client certificate of '{}' was revoked.",
+                device_name
+            )));
+        }
+
+        Ok(verified)
+    }
+}
+
+pub fn client_cert_verifier(ca_cert_loc: &str) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    if !Path::new(ca_cert_loc).exists() {
+        return Err(format!(
+            "The client CA certificate doesn't exist at '{}', please run 'rclip-server ca init' first.",
+            ca_cert_loc
+        ));
+    }
+
+    let mut roots = RootCertStore::empty();
+
+    for cert_der in rclip_config::certs::load_certificates(ca_cert_loc)? {
+        roots
+            .add(&TlsCertificate(cert_der))
+            .map_err(|e| format!("Invalid client CA certificate in '{}'. {}", ca_cert_loc, e))?;
+    }
+
+    Ok(Arc::new(RevocationCheckingVerifier {
+        inner: AllowAnyAuthenticatedClient::new(roots),
+        revoked_path: revocation_list(Path::new(ca_cert_loc)),
+    }))
+}
+
+// The revocation list lives next to the certificate of a CA created by `ca init`, wherever its
+// data directory is. Other CAs don't have any.
+fn revocation_list(ca_cert_path: &Path) -> Option<PathBuf> {
+    let ca_dir = ca_cert_path.parent()?;
+
+    if ca_cert_path.file_name()? == FILENAME_CA_CERT && ca_dir.join(FILENAME_CA_KEY).exists() {
+        Some(ca_dir.join(FILENAME_REVOKED))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let ret = std::env::temp_dir().join(format!("rclip-ca-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&ret);
+        fs::create_dir_all(&ret).unwrap();
+        ret
+    }
+
+    fn verify(verifier: &Arc<dyn ClientCertVerifier>, cert_path: &Path) -> Result<ClientCertVerified, rustls::Error> {
+        let cert_der = fs::read(cert_path).unwrap();
+        verifier.verify_client_cert(&TlsCertificate(cert_der), &[], SystemTime::now())
+    }
+
+    #[test]
+    fn rejects_revoked_certificates() {
+        let ca_dir = temp_dir("revoke");
+        let (ca_cert_path, _) = create_ca(&ca_dir, DEFAULT_CA_NAME, 1, false).unwrap();
+        assert!(create_ca(&ca_dir, DEFAULT_CA_NAME, 1, false).is_err());
+
+        let laptop = issue_certificate(&ca_dir, "laptop", 1, &ca_dir, false).unwrap();
+        let phone = issue_certificate(&ca_dir, "phone", 1, &ca_dir, false).unwrap();
+        assert!(issue_certificate(&ca_dir, "laptop", 1, &ca_dir, false).is_err());
+
+        let verifier = client_cert_verifier(&ca_cert_path.display().to_string()).unwrap();
+        assert!(verify(&verifier, &laptop.cert_path).is_ok());
+        assert!(verify(&verifier, &phone.cert_path).is_ok());
+
+        assert_eq!(revoke_device(&ca_dir, "laptop").unwrap(), vec![laptop.fingerprint]);
+        assert!(revoke_device(&ca_dir, "laptop").is_err());
+
+        let err = verify(&verifier, &laptop.cert_path).unwrap_err();
+        assert_eq!(err.to_string(), "unexpected error: The client certificate of 'laptop' was revoked.");
+        assert!(verify(&verifier, &phone.cert_path).is_ok());
+
+        // A new certificate can then be issued for the device
+        let laptop = issue_certificate(&ca_dir, "laptop", 1, &ca_dir, false).unwrap();
+        assert!(verify(&verifier, &laptop.cert_path).is_ok());
+
+        let _ = fs::remove_dir_all(&ca_dir);
+    }
+
+    #[test]
+    fn rejects_certificates_of_other_cas() {
+        let ca_dir = temp_dir("trusted");
+        let other_ca_dir = temp_dir("other");
+        let (ca_cert_path, _) = create_ca(&ca_dir, DEFAULT_CA_NAME, 1, false).unwrap();
+        create_ca(&other_ca_dir, "Other CA", 1, false).unwrap();
+
+        let issued = issue_certificate(&other_ca_dir, "laptop", 1, &other_ca_dir, false).unwrap();
+        let verifier = client_cert_verifier(&ca_cert_path.display().to_string()).unwrap();
+        assert!(verify(&verifier, &issued.cert_path).is_err());
+
+        let _ = fs::remove_dir_all(&ca_dir);
+        let _ = fs::remove_dir_all(&other_ca_dir);
+    }
+
+    #[test]
+    fn only_built_in_cas_have_a_revocation_list() {
+        let ca_dir = temp_dir("external");
+        let (ca_cert_path, _) = create_ca(&ca_dir, DEFAULT_CA_NAME, 1, false).unwrap();
+        let issued = issue_certificate(&ca_dir, "laptop", 1, &ca_dir, false).unwrap();
+        revoke_device(&ca_dir, "laptop").unwrap();
+
+        assert_eq!(revocation_list(&ca_cert_path), Some(ca_dir.join(FILENAME_REVOKED)));
+
+        // The same CA, configured as an external one with client-ca-file
+        let external_path = ca_dir.join("client-ca.der");
+        fs::copy(&ca_cert_path, &external_path).unwrap();
+        assert_eq!(revocation_list(&external_path), None);
+
+        let verifier = client_cert_verifier(&external_path.display().to_string()).unwrap();
+        assert!(verify(&verifier, &issued.cert_path).is_ok());
+
+        let _ = fs::remove_dir_all(&ca_dir);
+    }
+}
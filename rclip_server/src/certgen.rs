@@ -0,0 +1,244 @@
+// Self-signed certificate generation (`gen-cert` subcommand), replacing the openssl scripts.
+// The public certificate and the PKCS#8 private key are written in DER format.
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, KeyPair, SanType};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use time::{Duration, OffsetDateTime};
+
+pub const SUBCOMMAND_GEN_CERT: &str = "gen-cert";
+
+pub const KEY_TYPE_RSA: &str     = "rsa";
+pub const KEY_TYPE_ECDSA: &str   = "ecdsa";
+pub const KEY_TYPE_ED25519: &str = "ed25519";
+
+const DEFAULT_SANS: [&str; 3]   = ["localhost", "127.0.0.1", "10.0.2.2"];
+const DEFAULT_VALIDITY_DAYS: &str = "365";
+const DEFAULT_RSA_BITS: &str      = "4096";
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name(SUBCOMMAND_GEN_CERT)
+        .about("Generates a self-signed certificate and its private key")
+        .arg(
+            Arg::with_name("key-type")
+                .long("key-type")
+                .help("Type of private key")
+                .required(false)
+                .possible_values(&[KEY_TYPE_RSA, KEY_TYPE_ECDSA, KEY_TYPE_ED25519])
+                .default_value(KEY_TYPE_ECDSA)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rsa-bits")
+                .long("rsa-bits")
+                .help("Size of RSA keys in bits")
+                .required(false)
+                .possible_values(&["2048", "3072", "4096"])
+                .default_value(DEFAULT_RSA_BITS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("san")
+                .long("san")
+                .help("Host name or IP address of the server (subject alternative name), can be repeated [default: localhost, 127.0.0.1, 10.0.2.2]")
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("days")
+                .long("days")
+                .help("Validity period in days")
+                .required(false)
+                .default_value(DEFAULT_VALIDITY_DAYS)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-dir")
+                .long("output-dir")
+                .help("Destination folder [default: rclip data directory]")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .help("Overwrite existing certificate files")
+                .required(false)
+                .takes_value(false),
+        )
+}
+
+pub fn run(matches: &ArgMatches, filename_cert_pub: &str, filename_cert_priv: &str) -> Result<(), Box<dyn Error>> {
+    let sans: Vec<String> = match matches.values_of("san") {
+        Some(values) => values.map(|v| rclip_config::unbracket_host(v).to_string()).collect(),
+        None => DEFAULT_SANS.iter().map(|v| v.to_string()).collect(),
+    };
+
+    let days = matches
+        .value_of("days")
+        .unwrap_or(DEFAULT_VALIDITY_DAYS)
+        .parse::<u32>()
+        .map_err(|e| format!("Invalid number of days. {}", e))?;
+
+    if days == 0 {
+        return Err("The validity period must be at least one day.".into());
+    }
+
+    let rsa_bits = matches.value_of("rsa-bits").unwrap_or(DEFAULT_RSA_BITS).parse::<usize>()?;
+    let key_type = matches.value_of("key-type").unwrap_or(KEY_TYPE_ECDSA);
+
+    let output_dir = match matches.value_of("output-dir") {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| format!("Couldn't create output folder: {}. {}", dir, e))?;
+            PathBuf::from(dir)
+        }
+        None => rclip_config::create_data_dir()?,
+    };
+
+    let cert_pub_path = output_dir.join(filename_cert_pub);
+    let cert_priv_path = output_dir.join(filename_cert_priv);
+
+    if !matches.is_present("force") {
+        for path in [&cert_pub_path, &cert_priv_path] {
+            if path.exists() {
+                return Err(format!("The file '{}' already exists, use --force to overwrite it.", path.display()).into());
+            }
+        }
+    }
+
+    if key_type == KEY_TYPE_RSA {
+        println!("Generating a {} bits RSA key, this may take a while...", rsa_bits);
+    }
+
+    let (cert_der, key_der) = generate(key_type, rsa_bits, &sans, days)?;
+
+    write_file(&cert_pub_path, &cert_der, false)?;
+    write_file(&cert_priv_path, &key_der, true)?;
+
+    println!("Generated {} certificate for: {}.", key_type, sans.join(", "));
+    println!("Public certificate: {}", cert_pub_path.display());
+    println!("Fingerprint: {}", rclip_config::certs::fingerprint(&cert_der));
+    println!("Private key: {}", cert_priv_path.display());
+
+    Ok(())
+}
+
+// Returns the DER encoded certificate and PKCS#8 private key.
+pub fn generate(key_type: &str, rsa_bits: usize, sans: &[String], days: u32) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+    if sans.is_empty() {
+        return Err("At least one host name or IP address is required.".into());
+    }
+
+    let mut params = CertificateParams::default();
+
+    params.subject_alt_names = sans
+        .iter()
+        .map(|san| match san.parse::<IpAddr>() {
+            Ok(ip) => SanType::IpAddress(ip),
+            Err(_) => SanType::DnsName(san.clone()),
+        })
+        .collect();
+
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, sans[0].clone());
+
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now;
+    params.not_after = now + Duration::days(days.into());
+
+    match key_type {
+        KEY_TYPE_RSA => {
+            use rsa::pkcs8::EncodePrivateKey;
+
+            // ring cannot generate RSA keys, only sign with them
+            let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), rsa_bits)
+                .map_err(|e| format!("Could not generate RSA key. {}", e))?;
+            let key_der = key
+                .to_pkcs8_der()
+                .map_err(|e| format!("Could not encode RSA key. {}", e))?;
+
+            params.alg = &rcgen::PKCS_RSA_SHA256;
+            params.key_pair = Some(KeyPair::from_der_and_sign_algo(key_der.as_bytes(), &rcgen::PKCS_RSA_SHA256)?);
+        }
+        KEY_TYPE_ECDSA => params.alg = &rcgen::PKCS_ECDSA_P256_SHA256,
+        KEY_TYPE_ED25519 => params.alg = &rcgen::PKCS_ED25519,
+        _ => return Err(format!("Unsupported key type '{}'.", key_type).into()),
+    }
+
+    let cert = Certificate::from_params(params)?;
+
+    Ok((cert.serialize_der()?, cert.serialize_private_key_der()))
+}
+
+pub fn write_file(path: &Path, data: &[u8], private: bool) -> Result<(), Box<dyn Error>> {
+    let mut options = fs::OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    #[cfg(not(unix))]
+    let _ = private;
+
+    let mut f = options
+        .open(path)
+        .map_err(|e| format!("Could not create '{}'. {}", path.display(), e))?;
+
+    f.write_all(data)
+        .map_err(|e| format!("Could not write '{}'. {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+    use x509_parser::extensions::GeneralName;
+
+    #[test]
+    fn generates_loadable_certificates() {
+        let sans = ["rclip.example.org".to_string(), "10.0.2.2".to_string(), "::1".to_string()];
+
+        for key_type in [KEY_TYPE_RSA, KEY_TYPE_ECDSA, KEY_TYPE_ED25519] {
+            let (cert_der, key_der) = generate(key_type, 2048, &sans, 30).unwrap();
+            let identity = Identity::from_der(key_type, key_type, vec![cert_der.clone()], key_der)
+                .unwrap_or_else(|e| panic!("Could not load the {} certificate: {}", key_type, e));
+
+            assert_eq!(identity.certificate, cert_der);
+            assert!((29..=30).contains(&identity.days_to_expiry()), "{}: {}", key_type, identity.days_to_expiry());
+
+            let (_, cert) = x509_parser::parse_x509_certificate(&cert_der).unwrap();
+            assert!(cert.validity().is_valid(), "{}", key_type);
+
+            let names: Vec<String> = cert
+                .subject_alternative_name()
+                .unwrap()
+                .expect("Missing subject alternative names")
+                .value
+                .general_names
+                .iter()
+                .map(|name| match name {
+                    GeneralName::DNSName(name) => name.to_string(),
+                    GeneralName::IPAddress(ip) if ip.len() == 4 => IpAddr::from(<[u8; 4]>::try_from(*ip).unwrap()).to_string(),
+                    GeneralName::IPAddress(ip) => IpAddr::from(<[u8; 16]>::try_from(*ip).unwrap()).to_string(),
+                    other => panic!("Unexpected subject alternative name {:?}", other),
+                })
+                .collect();
+            assert_eq!(names, sans, "{}", key_type);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert!(generate(KEY_TYPE_ECDSA, 0, &[], 1).is_err());
+        assert!(generate("dsa", 0, &["localhost".to_string()], 1).is_err());
+    }
+}
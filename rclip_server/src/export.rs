@@ -0,0 +1,117 @@
+// Connection bundles for new clients (`export-client-bundle` subcommand), holding the server
+// address, its certificate fingerprint (or the certificate itself) and an optional client token.
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rclip_config::bundle::ClientBundle;
+use std::error::Error;
+use std::net::IpAddr;
+use std::path::Path;
+
+pub const SUBCOMMAND_EXPORT_CLIENT_BUNDLE: &str = "export-client-bundle";
+
+const DEFAULT_CLIENT_NAME: &str = "bundle";
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name(SUBCOMMAND_EXPORT_CLIENT_BUNDLE)
+        .about("Prints an rclip:// URI, or writes a file, with everything a client needs to connect")
+        .arg(
+            Arg::with_name("host")
+                .long("host")
+                .help("Server host as reached by clients [default: configured server host]")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .help("Server port as reached by clients [default: configured server port]")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .help("Bundle file to write instead of printing a URI")
+                .required(false)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("embed-certificate")
+                .long("embed-certificate")
+                .help("Include the server certificate in the bundle file, in addition to its fingerprint")
+                .required(false)
+                .requires("output")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("issue-token")
+                .long("issue-token")
+                .help("Issue a client token, for servers requiring authentication")
+                .required(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .help("Name of the client token on the server")
+                .required(false)
+                .default_value(DEFAULT_CLIENT_NAME)
+                .takes_value(true),
+        )
+}
+
+pub fn run(matches: &ArgMatches, server: &rclip_config::Server, key_pub_loc: &str) -> Result<(), Box<dyn Error>> {
+    let host = matches
+        .value_of("host")
+        .map(|host| host.to_string())
+        .or_else(|| server.host.clone())
+        .ok_or("Please provide the server host argument for --host.")?;
+
+    // Clients cannot connect to a wildcard address
+    if rclip_config::unbracket_host(&host)
+        .parse::<IpAddr>()
+        .map(|ip| ip.is_unspecified())
+        .unwrap_or(false)
+    {
+        return Err(format!("The server listens on '{}', please provide the address used by clients with --host.", host).into());
+    }
+
+    let port = match matches.value_of("port") {
+        Some(port) => port.parse::<u32>()?,
+        None => server.port.unwrap_or(rclip_config::DEFAULT_SERVER_PORT),
+    };
+
+    // Only the first certificate of a chain identifies the server
+    let cert_der = rclip_config::certs::load_certificates(key_pub_loc)?.remove(0);
+
+    let token = if matches.is_present("issue-token") {
+        Some(crate::auth::issue_token(matches.value_of("name").unwrap_or(DEFAULT_CLIENT_NAME))?)
+    } else {
+        None
+    };
+
+    let bundle = ClientBundle {
+        fingerprints: vec![rclip_config::certs::fingerprint(&cert_der)],
+        certificate: if matches.is_present("embed-certificate") {
+            Some(rclip_config::certs::to_pem(&cert_der))
+        } else {
+            None
+        },
+        token,
+        server: rclip_config::Server {
+            host: Some(host),
+            port: Some(port),
+        },
+    };
+
+    match matches.value_of("output") {
+        Some(output) => {
+            // The token is a secret
+            crate::certgen::write_file(Path::new(output), bundle.to_toml()?.as_bytes(), bundle.token.is_some())?;
+            println!("Connection bundle written to '{}'.", output);
+        }
+        None => println!("{}", bundle.to_uri()?),
+    }
+
+    Ok(())
+}
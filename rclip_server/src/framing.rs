@@ -0,0 +1,254 @@
+// Framing for persistent byte channels (virtio-serial ports, QEMU character devices),
+// which never signal the end of a session by closing the connection.
+//
+// Each frame is a type byte, a big-endian u32 payload length and the payload:
+// - START: opens a session, the server acknowledges it with its own START frame
+// - DATA:  carries TLS records
+// - END:   no more data will be sent by this peer for the current session
+//
+// A START frame received in the middle of a session aborts it, which allows a client
+// that died mid-session to be replaced without restarting the server.
+
+use std::future::Future;
+use std::io;
+use tokio::io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+
+pub const FRAME_START: u8 = b'S';
+pub const FRAME_DATA: u8  = b'D';
+pub const FRAME_END: u8   = b'E';
+
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+const FRAME_HEADER_LEN: usize = 5;
+const START_FRAME: [u8; FRAME_HEADER_LEN] = [FRAME_START, 0, 0, 0, 0];
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    reader.read_exact(&mut header).await?;
+
+    let kind = header[0];
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+
+    if ![FRAME_START, FRAME_DATA, FRAME_END].contains(&kind) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown frame type {:#x}", kind)));
+    }
+
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame too large ({} bytes)", len)));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+
+    Ok((kind, payload))
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+// Skips any stale bytes until a START frame is found.
+async fn wait_for_start<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<()> {
+    let mut window = [0xffu8; FRAME_HEADER_LEN];
+
+    loop {
+        window.rotate_left(1);
+        window[FRAME_HEADER_LEN - 1] = reader.read_u8().await?;
+
+        if window == START_FRAME {
+            return Ok(());
+        }
+    }
+}
+
+// Serves consecutive sessions over a persistent channel, until it fails or reaches EOF.
+// Each session receives one end of an in-memory pipe, the other end being pumped to/from frames.
+pub async fn serve_channel<S, F, Fut>(channel: S, mut session: F) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+    F: FnMut(DuplexStream) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let (mut reader, mut writer) = split(channel);
+    let mut synced = false;
+
+    loop {
+        if !synced {
+            wait_for_start(&mut reader).await?;
+        }
+
+        write_frame(&mut writer, FRAME_START, &[]).await?;
+
+        let (session_io, pump_io) = tokio::io::duplex(MAX_FRAME_LEN);
+        let (mut pump_reader, mut pump_writer) = split(pump_io);
+
+        let outbound = async {
+            let mut buf = vec![0u8; MAX_FRAME_LEN];
+
+            loop {
+                let n = pump_reader.read(&mut buf).await?;
+
+                if n == 0 {
+                    return write_frame(&mut writer, FRAME_END, &[]).await;
+                }
+
+                write_frame(&mut writer, FRAME_DATA, &buf[..n]).await?;
+            }
+        };
+
+        // Resolves to true when a new session was requested before the end of the current one
+        let inbound = async {
+            loop {
+                let (kind, payload) = read_frame(&mut reader).await?;
+
+                // Write failures only mean that the session is already over
+                match kind {
+                    FRAME_DATA => {
+                        let _ = pump_writer.write_all(&payload).await;
+                    }
+                    FRAME_END => {
+                        let _ = pump_writer.shutdown().await;
+                        return Ok::<bool, io::Error>(false);
+                    }
+                    _ => return Ok(true),
+                }
+            }
+        };
+
+        let work = async {
+            let (_, outbound_result) = tokio::join!(session(session_io), outbound);
+            outbound_result
+        };
+
+        tokio::pin!(inbound);
+        tokio::pin!(work);
+
+        let mut work_result = None;
+
+        let inbound_result = loop {
+            tokio::select! {
+                result = &mut inbound => break result,
+                result = &mut work, if work_result.is_none() => work_result = Some(result),
+            }
+        };
+
+        match inbound_result {
+            Ok(true) => synced = true,
+            Ok(false) => {
+                // The peer is done, let the session flush its remaining output
+                match work_result {
+                    Some(result) => result?,
+                    None => work.await?,
+                }
+
+                synced = false;
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("Lost framing synchronization: {}.", e);
+                synced = false;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn frame_bytes(kind: u8, payload: &[u8]) -> Vec<u8> {
+        let mut ret = Vec::new();
+        write_frame(&mut ret, kind, payload).await.unwrap();
+        ret
+    }
+
+    #[tokio::test]
+    async fn round_trips_frames() {
+        let payload = vec![0x17; MAX_FRAME_LEN];
+        let mut bytes = frame_bytes(FRAME_DATA, &payload).await;
+        bytes.extend(frame_bytes(FRAME_END, &[]).await);
+
+        assert_eq!(&bytes[..FRAME_HEADER_LEN], &[FRAME_DATA, 0, 1, 0, 0]);
+
+        let mut reader = &bytes[..];
+        assert_eq!(read_frame(&mut reader).await.unwrap(), (FRAME_DATA, payload));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), (FRAME_END, Vec::new()));
+        assert!(reader.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_frames() {
+        let bytes = frame_bytes(FRAME_DATA, b"record").await;
+
+        for len in [2, FRAME_HEADER_LEN, bytes.len() - 1] {
+            let err = read_frame(&mut &bytes[..len]).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_and_unknown_frames() {
+        let mut bytes = vec![FRAME_DATA];
+        bytes.extend_from_slice(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+        bytes.extend(vec![0; MAX_FRAME_LEN + 1]);
+
+        let err = read_frame(&mut &bytes[..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = read_frame(&mut &[b'X', 0, 0, 0, 0][..]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn skips_stale_bytes_until_start() {
+        let mut bytes = vec![FRAME_START, 0, 0, FRAME_DATA, 0xff];
+        bytes.extend(START_FRAME);
+        bytes.extend(frame_bytes(FRAME_END, &[]).await);
+
+        let mut reader = &bytes[..];
+        wait_for_start(&mut reader).await.unwrap();
+        assert_eq!(read_frame(&mut reader).await.unwrap(), (FRAME_END, Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn serves_consecutive_sessions() {
+        let (channel, mut peer) = tokio::io::duplex(4 * MAX_FRAME_LEN);
+        let server = tokio::spawn(serve_channel(channel, |mut session: DuplexStream| async move {
+            let mut request = Vec::new();
+            session.read_to_end(&mut request).await.unwrap();
+            request.reverse();
+            session.write_all(&request).await.unwrap();
+        }));
+
+        for request in [&b"abc"[..], b"0123456789"] {
+            peer.write_all(b"stale").await.unwrap();
+            write_frame(&mut peer, FRAME_START, &[]).await.unwrap();
+            write_frame(&mut peer, FRAME_DATA, request).await.unwrap();
+            write_frame(&mut peer, FRAME_END, &[]).await.unwrap();
+
+            assert_eq!(read_frame(&mut peer).await.unwrap(), (FRAME_START, Vec::new()));
+
+            let mut response = Vec::new();
+
+            loop {
+                match read_frame(&mut peer).await.unwrap() {
+                    (FRAME_DATA, payload) => response.extend(payload),
+                    (kind, _) => {
+                        assert_eq!(kind, FRAME_END);
+                        break;
+                    }
+                }
+            }
+
+            assert_eq!(response, request.iter().rev().copied().collect::<Vec<u8>>());
+        }
+
+        drop(peer);
+        assert_eq!(server.await.unwrap().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,115 @@
+// Server certificates and their private keys, held behind a resolver so that
+// a rotated certificate is served to new connections without any restart.
+
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::{self, CertifiedKey};
+use tokio_rustls::rustls::{Certificate, PrivateKey};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+pub struct Identity {
+    pub key_pub_loc: String,
+    pub key_priv_loc: String,
+    certified_key: Arc<CertifiedKey>,
+    // DER encoded server certificate, the first one of the chain
+    pub certificate: Vec<u8>,
+    pub fingerprint: String,
+    // Unix timestamp of the end of the validity period
+    pub not_after: i64,
+}
+
+impl Identity {
+    pub fn load(key_pub_loc: &str, key_priv_loc: &str) -> Result<Self, String> {
+        let key_priv_bytes = rclip_config::certs::load_private_key(key_priv_loc)?;
+        let certs = rclip_config::certs::load_certificates(key_pub_loc)?;
+
+        Self::from_der(key_pub_loc, key_priv_loc, certs, key_priv_bytes)
+    }
+
+    // The locations only name the certificate and its key in messages, unless loaded from files.
+    pub fn from_der(key_pub_loc: &str, key_priv_loc: &str, certs: Vec<Vec<u8>>, key_priv_bytes: Vec<u8>) -> Result<Self, String> {
+        let certs: Vec<Certificate> = certs.into_iter().map(Certificate).collect();
+
+        let signing_key = sign::any_supported_type(&PrivateKey(key_priv_bytes))
+            .map_err(|_| format!("Unsupported private key type in '{}'.", key_priv_loc))?;
+
+        let certificate = certs
+            .first()
+            .map(|cert| cert.0.clone())
+            .ok_or_else(|| format!("No certificate found in '{}'.", key_pub_loc))?;
+        let (_, cert) = x509_parser::parse_x509_certificate(&certificate)
+            .map_err(|e| format!("Invalid certificate in '{}'. {}", key_pub_loc, e))?;
+        let not_after = cert.validity().not_after.timestamp();
+
+        Ok(Self {
+            key_pub_loc: key_pub_loc.to_string(),
+            key_priv_loc: key_priv_loc.to_string(),
+            certified_key: Arc::new(CertifiedKey::new(certs, signing_key)),
+            fingerprint: rclip_config::certs::fingerprint(&certificate),
+            certificate,
+            not_after,
+        })
+    }
+
+    // Negative once the certificate has expired.
+    pub fn days_to_expiry(&self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        (self.not_after - now).div_euclid(SECONDS_PER_DAY)
+    }
+}
+
+// Serves the current certificate, and keeps track of the next one when a rotation is prepared.
+pub struct IdentityResolver {
+    current: RwLock<Arc<Identity>>,
+    next: RwLock<Option<Arc<Identity>>>,
+}
+
+impl IdentityResolver {
+    pub fn new(identity: Identity) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(identity)),
+            next: RwLock::new(None),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Identity> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn next(&self) -> Option<Arc<Identity>> {
+        match self.next.read() {
+            Ok(next) => next.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn set_next(&self, identity: Option<Identity>) {
+        if let Ok(mut next) = self.next.write() {
+            *next = identity.map(Arc::new);
+        }
+    }
+
+    // Serves the next certificate from now on.
+    pub fn promote_next(&self, identity: Identity) {
+        if let Ok(mut current) = self.current.write() {
+            *current = Arc::new(identity);
+        }
+
+        self.set_next(None);
+    }
+}
+
+impl ResolvesServerCert for IdentityResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current().certified_key.clone())
+    }
+}
@@ -0,0 +1,465 @@
+// Clipboard server, for programs embedding it as well as the rclip-server command-line application.
+//
+// RclipServer::builder() describes the listeners, their TLS identity and the clipboard store,
+// spawn() then serves them on the current tokio runtime until the returned handle stops them.
+
+use std::error::Error;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+use tokio_rustls::rustls::server::ClientCertVerifier;
+
+pub mod ca;
+pub mod certgen;
+pub mod export;
+pub mod pairing;
+pub mod store;
+pub mod systemd;
+
+mod auth;
+mod framing;
+mod identity;
+mod rotation;
+mod service;
+mod transport;
+
+use identity::{Identity, IdentityResolver};
+use service::Service;
+
+pub use bytes::Bytes;
+pub use rotation::RotationPolicy;
+pub use store::{ClipboardStore, FileStore, MemoryStore};
+pub use transport::LocalAddr;
+
+pub const FILENAME_DER_CERT_PRIV: &str = "der-cert-priv.der";
+
+pub const DEFAULT_MAX_SIZE: usize = 10 * 1024 * 1024;
+
+// Certificate chain and private key served by the listeners, loaded from files (PEM or DER) or given as DER bytes.
+#[derive(Clone, PartialEq)]
+pub struct ServerTls {
+    source: TlsSource,
+}
+
+#[derive(Clone, PartialEq)]
+enum TlsSource {
+    Files { key_pub_loc: String, key_priv_loc: String },
+    Der { certs: Vec<Vec<u8>>, key_priv: Vec<u8> },
+}
+
+impl ServerTls {
+    pub fn from_files(key_pub_loc: &str, key_priv_loc: &str) -> Self {
+        Self {
+            source: TlsSource::Files {
+                key_pub_loc: key_pub_loc.to_string(),
+                key_priv_loc: key_priv_loc.to_string(),
+            },
+        }
+    }
+
+    // The server certificate comes first in the chain.
+    pub fn from_der(certs: Vec<Vec<u8>>, key_priv: Vec<u8>) -> Self {
+        Self {
+            source: TlsSource::Der { certs, key_priv },
+        }
+    }
+
+    fn load(&self) -> Result<Identity, String> {
+        match &self.source {
+            TlsSource::Files { key_pub_loc, key_priv_loc } => {
+                rotation::complete_interrupted_switch(key_pub_loc, key_priv_loc)?;
+                Identity::load(key_pub_loc, key_priv_loc)
+            }
+            TlsSource::Der { certs, key_priv } => {
+                Identity::from_der("in-memory certificate", "in-memory private key", certs.clone(), key_priv.clone())
+            }
+        }
+    }
+}
+
+// Address to listen on (host:port, [ipv6]:port, vsock:CID:PORT, unix:PATH or device:PATH), with the settings
+// of the server applying unless overridden.
+pub struct ListenerOptions {
+    host: String,
+    port: u32,
+    dual_stack: bool,
+    tls: Option<ServerTls>,
+    require_token: Option<bool>,
+    require_client_cert: Option<bool>,
+}
+
+impl ListenerOptions {
+    // Port 0 picks an ephemeral port, as reported by ServerHandle::local_addrs. Ports above 65535 are
+    // only valid for vsock addresses.
+    pub fn new(host: &str, port: u32) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            dual_stack: false,
+            tls: None,
+            require_token: None,
+            require_client_cert: None,
+        }
+    }
+
+    pub fn dual_stack(mut self, dual_stack: bool) -> Self {
+        self.dual_stack = dual_stack;
+        self
+    }
+
+    pub fn tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn require_token(mut self, require_token: bool) -> Self {
+        self.require_token = Some(require_token);
+        self
+    }
+
+    pub fn require_client_cert(mut self, require_client_cert: bool) -> Self {
+        self.require_client_cert = Some(require_client_cert);
+        self
+    }
+
+    fn matches(&self, addr: &LocalAddr) -> bool {
+        match addr {
+            LocalAddr::Tcp(addr) => {
+                self.port == u32::from(addr.port())
+                    && rclip_config::unbracket_host(&self.host)
+                        .parse::<IpAddr>()
+                        .map(|ip| ip == addr.ip())
+                        .unwrap_or(false)
+            }
+            LocalAddr::Device(path) => self.host.strip_prefix(rclip_config::DEVICE_PREFIX) == Some(path),
+            #[cfg(target_os = "linux")]
+            LocalAddr::Vsock(cid, port) => {
+                self.port == *port
+                    && matches!(rclip_config::parse_vsock_cid(&self.host), Ok(Some(own_cid)) if own_cid == *cid)
+            }
+            #[cfg(unix)]
+            LocalAddr::Unix(path) => self.host.strip_prefix(rclip_config::UNIX_PREFIX) == Some(path),
+        }
+    }
+}
+
+pub struct RclipServer;
+
+impl RclipServer {
+    pub fn builder() -> RclipServerBuilder {
+        RclipServerBuilder::default()
+    }
+}
+
+pub struct RclipServerBuilder {
+    tls: Option<ServerTls>,
+    listeners: Vec<ListenerOptions>,
+    activated_listeners: Vec<socket2::Socket>,
+    store: Option<Arc<dyn ClipboardStore>>,
+    tls_policy: rclip_config::tls::TlsPolicy,
+    client_cert_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    require_token: bool,
+    max_size: usize,
+    rotation_policy: Option<RotationPolicy>,
+    verbose: bool,
+}
+
+impl Default for RclipServerBuilder {
+    fn default() -> Self {
+        Self {
+            tls: None,
+            listeners: Vec::new(),
+            activated_listeners: Vec::new(),
+            store: None,
+            tls_policy: rclip_config::tls::TlsPolicy::default(),
+            client_cert_verifier: None,
+            require_token: false,
+            max_size: DEFAULT_MAX_SIZE,
+            rotation_policy: None,
+            verbose: false,
+        }
+    }
+}
+
+impl RclipServerBuilder {
+    // Identity of the listeners without their own.
+    pub fn tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn listener(mut self, listener: ListenerOptions) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    // Sockets already listening, i.e. passed by systemd, served instead of binding the listeners.
+    // Each one gets the settings of the listener with the same address, or of the only listener.
+    pub fn activated_listeners(mut self, listeners: Vec<socket2::Socket>) -> Self {
+        self.activated_listeners = listeners;
+        self
+    }
+
+    // Private memory by default.
+    pub fn store(mut self, store: Arc<dyn ClipboardStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn tls_policy(mut self, tls_policy: rclip_config::tls::TlsPolicy) -> Self {
+        self.tls_policy = tls_policy;
+        self
+    }
+
+    // Client certificates are then required by the listeners, unless they opt out.
+    pub fn client_cert_verifier(mut self, verifier: Arc<dyn ClientCertVerifier>) -> Self {
+        self.client_cert_verifier = Some(verifier);
+        self
+    }
+
+    pub fn require_token(mut self, require_token: bool) -> Self {
+        self.require_token = require_token;
+        self
+    }
+
+    // Largest request accepted, in bytes.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    // Certificates are only monitored for expiry and rotated with a policy.
+    pub fn rotation_policy(mut self, rotation_policy: RotationPolicy) -> Self {
+        self.rotation_policy = Some(rotation_policy);
+        self
+    }
+
+    // Prints the certificate fingerprints and the listening addresses.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    // Binds the listeners and serves them on the current tokio runtime.
+    pub async fn spawn(mut self) -> Result<ServerHandle, Box<dyn Error>> {
+        if self.listeners.is_empty() {
+            return Err("Please provide at least one listener.".into());
+        }
+
+        // Reports an unsupported TLS policy before listening on any address
+        rclip_config::tls::apply(tokio_rustls::rustls::ServerConfig::builder(), &self.tls_policy)?;
+
+        let app_name = option_env!("CARGO_PKG_NAME").unwrap_or("Unknown");
+        let clipboard = self.clipboard();
+        let mut identities = Vec::new();
+        let mut local_addrs = Vec::with_capacity(self.listeners.len());
+        let mut accept_loops = JoinSet::new();
+        let mut abort_handles = Vec::with_capacity(self.listeners.len());
+        let activated_listeners = std::mem::take(&mut self.activated_listeners);
+
+        if activated_listeners.is_empty() {
+            for options in &self.listeners {
+                let service = self.new_service(options, &mut identities, &clipboard)?;
+
+                if let Some(path) = options.host.strip_prefix(rclip_config::DEVICE_PREFIX) {
+                    let device = transport::Device::new(path)?;
+
+                    if self.verbose {
+                        println!("Starting '{}' on device '{}'.", app_name, path);
+                    }
+
+                    local_addrs.push(LocalAddr::Device(path.to_string()));
+                    abort_handles.push(accept_loops.spawn(service::serve_device(device, service)));
+                    continue;
+                }
+
+                let listener = service::bind(&options.host, options.port, options.dual_stack).await?;
+                let local_addr = listener.local_addr()?;
+
+                if self.verbose {
+                    println!("Starting '{}' on at '{}'.", app_name, local_addr);
+                }
+
+                local_addrs.push(local_addr);
+                abort_handles.push(accept_loops.spawn(service::accept_loop(listener, service)));
+            }
+        } else {
+            for activated_listener in activated_listeners {
+                let listener = transport::Listener::from_socket(activated_listener)?;
+                let local_addr = listener.local_addr()?;
+                let options = match self.listeners.iter().find(|options| options.matches(&local_addr)) {
+                    Some(options) => options,
+                    None if self.listeners.len() == 1 => &self.listeners[0],
+                    None => {
+                        return Err(format!(
+                            "The socket passed at '{}' matches none of the configured listeners.",
+                            local_addr
+                        )
+                        .into())
+                    }
+                };
+                let service = self.new_service(options, &mut identities, &clipboard)?;
+
+                if self.verbose {
+                    println!("Starting '{}' on at '{}' (socket activation).", app_name, local_addr);
+                }
+
+                local_addrs.push(local_addr);
+                abort_handles.push(accept_loops.spawn(service::accept_loop(listener, service)));
+            }
+        }
+
+        let rotation_monitor = self.rotation_policy.map(|policy| {
+            let resolvers = identities.into_iter().map(|(_, resolver)| resolver).collect();
+            rotation::spawn_monitor(resolvers, policy)
+        });
+
+        Ok(ServerHandle {
+            clipboard,
+            local_addrs,
+            accept_loops,
+            abort_handles,
+            rotation_monitor,
+        })
+    }
+
+    // Serves a single session on the standard input and output, i.e. from inetd or an SSH forced command.
+    // Without any TLS identity, the session is plain text, for channels that are already secured.
+    pub async fn serve_stdio(self) -> Result<(), Box<dyn Error>> {
+        let clipboard = self.clipboard();
+
+        let service = match &self.tls {
+            Some(_) => self.new_service(&ListenerOptions::new("stdio", 0), &mut Vec::new(), &clipboard)?,
+            None => Service {
+                acceptor: None,
+                clipboard,
+                identity: None,
+                require_token: self.require_token,
+                max_size: self.max_size,
+            },
+        };
+
+        service::handle_connection(transport::Stdio::new()?, service).await?;
+
+        Ok(())
+    }
+
+    fn clipboard(&self) -> Arc<dyn ClipboardStore> {
+        match &self.store {
+            Some(store) => store.clone(),
+            None => Arc::new(MemoryStore::default()),
+        }
+    }
+
+    fn new_service(
+        &self,
+        options: &ListenerOptions,
+        identities: &mut Vec<(ServerTls, Arc<IdentityResolver>)>,
+        clipboard: &Arc<dyn ClipboardStore>,
+    ) -> Result<Service, Box<dyn Error>> {
+        let tls = options
+            .tls
+            .as_ref()
+            .or(self.tls.as_ref())
+            .ok_or("Please provide a certificate and its private key.")?;
+        let identity = self.find_identity(tls, identities)?;
+
+        let client_cert_verifier = match options.require_client_cert {
+            Some(false) => None,
+            Some(true) => Some(self.client_cert_verifier.clone().ok_or("Please provide the CA of client certificates.")?),
+            None => self.client_cert_verifier.clone(),
+        };
+
+        service::new_service(
+            identity,
+            client_cert_verifier,
+            clipboard,
+            &self.tls_policy,
+            options.require_token.unwrap_or(self.require_token),
+            self.max_size,
+        )
+    }
+
+    // Listeners sharing the same certificate share their identity, which is rotated once.
+    fn find_identity(
+        &self,
+        tls: &ServerTls,
+        identities: &mut Vec<(ServerTls, Arc<IdentityResolver>)>,
+    ) -> Result<Arc<IdentityResolver>, Box<dyn Error>> {
+        if let Some((_, identity)) = identities.iter().find(|(known_tls, _)| known_tls == tls) {
+            return Ok(identity.clone());
+        }
+
+        let identity = tls.load()?;
+
+        // Clients may pin this value instead of getting a copy of the certificate
+        if self.verbose {
+            println!(
+                "Certificate fingerprint of '{}': {}",
+                identity.key_pub_loc, identity.fingerprint
+            );
+        }
+
+        let ret = Arc::new(IdentityResolver::new(identity));
+        identities.push((tls.clone(), ret.clone()));
+
+        Ok(ret)
+    }
+}
+
+// Running server, stopped by stop() or when the handle is dropped.
+pub struct ServerHandle {
+    clipboard: Arc<dyn ClipboardStore>,
+    local_addrs: Vec<LocalAddr>,
+    accept_loops: JoinSet<io::Result<()>>,
+    abort_handles: Vec<AbortHandle>,
+    rotation_monitor: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    // Bound addresses, in the order of the listeners.
+    pub fn local_addrs(&self) -> &[LocalAddr] {
+        &self.local_addrs
+    }
+
+    // Store of the served clipboard, i.e. to subscribe to its changes.
+    pub fn clipboard(&self) -> &Arc<dyn ClipboardStore> {
+        &self.clipboard
+    }
+
+    // Stops accepting connections, the sessions in progress still complete.
+    pub fn stop(&self) {
+        for abort_handle in &self.abort_handles {
+            abort_handle.abort();
+        }
+
+        if let Some(rotation_monitor) = &self.rotation_monitor {
+            rotation_monitor.abort();
+        }
+    }
+
+    // Waits until the server is stopped, or until any listener fails which stops the others.
+    pub async fn wait(mut self) -> Result<(), Box<dyn Error>> {
+        let mut ret = Ok(());
+
+        while let Some(accept_loop) = self.accept_loops.join_next().await {
+            let failure: Box<dyn Error> = match accept_loop {
+                Ok(Ok(())) => continue,
+                Err(e) if e.is_cancelled() => continue,
+                Ok(Err(e)) => e.into(),
+                Err(e) => e.into(),
+            };
+
+            // The remaining accept loops are cancelled, and drained by the next iterations
+            if ret.is_ok() {
+                self.stop();
+                ret = Err(failure);
+            }
+        }
+
+        self.stop();
+
+        ret
+    }
+}
@@ -1,27 +1,13 @@
 use clap::{App, Arg};
 use std::error::Error;
-use std::fs;
-use std::io;
 use std::path::Path;
-use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
+use std::sync::Arc;
 
-use std::sync::{Arc, Mutex};
-
-use tokio::net::TcpListener;
-use tokio_rustls::rustls::{self, Certificate, PrivateKey};
-
-use tokio_rustls::TlsAcceptor;
-
-const EMPTY_CLIPBOARD_TEXT: &str = "";
-    
-const CMD_READ: &str  = "READ:";
-const CMD_WRITE: &str = "WRITE:";
-const CMD_CLEAR: &str = "CLEAR:";
-
-const BUFFER_CAP: usize = 512;
+use rclip_server::{ca, certgen, export, pairing, store, systemd};
+use rclip_server::{ClipboardStore, ListenerOptions, RclipServer, RotationPolicy, ServerTls};
+use tokio_rustls::rustls::server::ClientCertVerifier;
 
 const FILENAME_CONFIG_SERVER: &str = "config-server.toml";
-const FILENAME_DER_CERT_PRIV: &str = "der-cert-priv.der";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -43,27 +29,77 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .help("Address to listen on (host:port, [ipv6]:port, vsock:CID:PORT, unix:PATH or device:PATH), can be repeated")
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["host", "port"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clipboard-backend")
+                .long("clipboard-backend")
+                .help("Clipboard storage: private memory, the system clipboard of this machine, or a file kept across restarts")
+                .required(false)
+                .possible_values(&[store::BACKEND_MEMORY, store::BACKEND_SYSTEM, store::BACKEND_FILE])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stdio")
+                .long("stdio")
+                .help("Serves a single session on the standard input and output, i.e. from inetd or an SSH forced command")
+                .required(false)
+                .conflicts_with_all(&["listen", "host", "port"])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no-tls")
+                .long("no-tls")
+                .help("With --stdio, exchanges plain text instead of TLS, for channels already encrypted and authenticated such as SSH")
+                .required(false)
+                .requires("stdio")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("der-cert-priv")
                 .long("der-cert-priv")
-                .help("Private DER certificate key")
+                .help("Private key: PKCS#1, PKCS#8 or SEC1 (PEM or DER)")
                 .required(false)
                 .takes_value(true),
         )
         .arg(
             Arg::with_name("der-cert-pub")
                 .long("der-cert-pub")
-                .help("Public DER certificate key")
-                .required(true)
+                .help("Public certificate or certificate chain (PEM or DER)")
+                .required(false)
                 .takes_value(true),
+        )
+        .subcommand(certgen::subcommand())
+        .subcommand(pairing::subcommand())
+        .subcommand(export::subcommand())
+        .subcommand(ca::subcommand());
+
+    let run_matches = app.get_matches();
+
+    if let Some(gen_cert_matches) = run_matches.subcommand_matches(certgen::SUBCOMMAND_GEN_CERT) {
+        return certgen::run(
+            gen_cert_matches,
+            rclip_config::DEFAULT_FILENAME_DER_CERT_PUB,
+            rclip_server::FILENAME_DER_CERT_PRIV,
         );
+    }
 
-    let run_matches = app.to_owned().get_matches();
+    if let Some(ca_matches) = run_matches.subcommand_matches(ca::SUBCOMMAND_CA) {
+        return ca::run(ca_matches);
+    }
 
     let mut server_config = match rclip_config::load_default_config(FILENAME_CONFIG_SERVER) {
         Ok(cfg) => cfg,
         Err(e) => {
-            eprintln!("Warn: Error parsing configuration file: {}!", e.to_string());
+            eprintln!("Warn: Error parsing configuration file: {}!", e);
             rclip_config::ServerConfig::default()
         }
     };
@@ -75,15 +111,17 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     if server_config.certificate.der_cert_priv.is_none() {
         server_config.certificate.der_cert_priv =
-            rclip_config::resolve_default_cert_path(FILENAME_DER_CERT_PRIV);
+            rclip_config::resolve_default_cert_path(rclip_server::FILENAME_DER_CERT_PRIV);
     }
 
+    let host_or_port_provided = run_matches.is_present("host") || run_matches.is_present("port");
+
     if let Some(proposed_host) = run_matches.value_of("host") {
         server_config.server.host = Some(proposed_host.to_string());
     }
 
     if let Some(proposed_port) = run_matches.value_of("port") {
-        server_config.server.port = Some(proposed_port.parse::<u16>()?)
+        server_config.server.port = Some(proposed_port.parse::<u32>()?)
     }
 
     if let Some(key_pub_loc) = run_matches.value_of("der-cert-pub") {
@@ -94,153 +132,185 @@ async fn main() -> Result<(), Box<dyn Error>> {
         server_config.certificate.der_cert_priv = Some(key_priv_loc.to_string());
     };
 
-    if server_config.certificate.der_cert_pub.is_none() {
-        return Err("Please provide the public certificate argument for --der-cert-pub.".into());
+    if let Some(backend) = run_matches.value_of("clipboard-backend") {
+        server_config.clipboard.backend = Some(backend.to_string());
     }
 
-    if server_config.certificate.der_cert_priv.is_none() {
-        return Err("Please provide the private certificate argument for --der-cert-priv.".into());
+    if let Some(pair_matches) = run_matches.subcommand_matches(pairing::SUBCOMMAND_PAIR) {
+        let key_pub_loc = server_config
+            .certificate
+            .der_cert_pub
+            .ok_or("Please provide the public certificate argument for --der-cert-pub.")?;
+
+        return pairing::run(pair_matches, &key_pub_loc);
     }
 
-    if let Some(key_loc) = server_config.certificate.der_cert_priv.clone() {
-        let key_path = Path::new(&key_loc);
+    if let Some(export_matches) = run_matches.subcommand_matches(export::SUBCOMMAND_EXPORT_CLIENT_BUNDLE) {
+        let key_pub_loc = server_config
+            .certificate
+            .der_cert_pub
+            .ok_or("Please provide the public certificate argument for --der-cert-pub.")?;
 
-        if !key_path.exists() {
-            return Err(format!("The private key file doesn't exists at '{}'!", &key_loc).into());
-        }
+        return export::run(export_matches, &server_config.server, &key_pub_loc);
     }
 
-    if let Some(key_loc) = server_config.certificate.der_cert_pub.clone() {
-        let key_path = Path::new(&key_loc);
+    if run_matches.is_present("stdio") {
+        return serve_stdio(server_config, !run_matches.is_present("no-tls")).await;
+    }
 
-        if !key_path.exists() {
-            return Err(format!("The public key file doesn't exists at '{}'!", &key_loc).into());
+    // Precedence: --listen addresses, then --host/--port, then [[listener]] entries, then [server]
+    let listeners = if let Some(addresses) = run_matches.values_of("listen") {
+        let mut ret = Vec::new();
+
+        for address in addresses {
+            let (host, port) = rclip_config::parse_address(address)?;
+            ret.push(rclip_config::Listener {
+                host: Some(host),
+                port: port.or(server_config.server.port),
+                ..Default::default()
+            });
         }
-    }
 
-    if let (Some(server_host), Some(server_port), Some(key_priv_loc), Some(key_pub_loc)) = (
-        server_config.server.host,
-        server_config.server.port,
-        server_config.certificate.der_cert_priv,
-        server_config.certificate.der_cert_pub,
-    ) {
-        serve(
-            app.get_name(),
-            server_host,
-            server_port,
-            key_priv_loc,
-            key_pub_loc,
-        )
-        .await
+        ret
+    } else if host_or_port_provided || server_config.listeners.is_empty() {
+        vec![rclip_config::Listener {
+            host: server_config.server.host.clone(),
+            port: server_config.server.port,
+            ..Default::default()
+        }]
     } else {
-        Err(
-            "Server error! Some required parameters were not provided: missing certificates?"
-                .into(),
-        )
+        server_config.listeners.clone()
+    };
+
+    let mut builder = RclipServer::builder();
+    let mut client_cert_verifier = None;
+
+    for listener in listeners {
+        let certificate = listener
+            .certificate
+            .unwrap_or_else(|| server_config.certificate.clone());
+
+        let key_pub_loc = certificate
+            .der_cert_pub
+            .or_else(|| server_config.certificate.der_cert_pub.clone())
+            .ok_or("Please provide the public certificate argument for --der-cert-pub.")?;
+
+        let key_priv_loc = certificate
+            .der_cert_priv
+            .or_else(|| server_config.certificate.der_cert_priv.clone())
+            .ok_or("Please provide the private certificate argument for --der-cert-priv.")?;
+
+        if !Path::new(&key_priv_loc).exists() {
+            return Err(format!("The private key file doesn't exists at '{}'!", &key_priv_loc).into());
+        }
+
+        if !Path::new(&key_pub_loc).exists() {
+            return Err(format!("The public key file doesn't exists at '{}'!", &key_pub_loc).into());
+        }
+
+        let require_client_cert = listener
+            .require_client_cert
+            .or(server_config.auth.require_client_cert)
+            .unwrap_or(false);
+
+        // All listeners requiring client certificates trust the same CA
+        if require_client_cert && client_cert_verifier.is_none() {
+            client_cert_verifier = Some(client_ca_verifier(&server_config.auth)?);
+        }
+
+        if let (Some(host), Some(port)) = (
+            listener.host.or_else(|| server_config.server.host.clone()),
+            listener.port.or(server_config.server.port),
+        ) {
+            builder = builder.listener(
+                ListenerOptions::new(&host, port)
+                    .dual_stack(listener.dual_stack.unwrap_or(false))
+                    .tls(ServerTls::from_files(&key_pub_loc, &key_priv_loc))
+                    .require_token(
+                        listener
+                            .require_token
+                            .or(server_config.auth.require_token)
+                            .unwrap_or(false),
+                    )
+                    .require_client_cert(require_client_cert),
+            );
+        } else {
+            return Err("Server error! Some required parameters were not provided: missing host or port?".into());
+        }
     }
-}
 
-async fn serve(
-    app_name: &str,
-    host: String,
-    port: u16,
-    key_priv_loc: String,
-    key_pub_loc: String,
-) -> Result<(), Box<dyn Error>> {
-    let key_priv_bytes = fs::read(key_priv_loc)?;
-    let key_pub_bytes = fs::read(key_pub_loc)?;
-    let config = rustls::ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(vec![Certificate(key_pub_bytes)], PrivateKey(key_priv_bytes))
-        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
-
-    let acceptor = TlsAcceptor::from(Arc::new(config));
-    let con_string = format!("{}:{}", host, port);
-    let listener = TcpListener::bind(con_string.clone()).await?;
-
-    println!("Starting '{}' on at '{}'.", app_name, con_string);
-
-    let clipboard = Arc::new(Mutex::new(String::new()));
-
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let acceptor = acceptor.clone();
-        let clipboard_copy = clipboard.clone();
-
-        tokio::spawn(async move {
-            let mut request = String::new();
-
-            match acceptor.accept(stream).await {
-                Ok(stream) => {
-                    let (mut reader, mut writer) = split(stream);
-
-                    loop {
-                        let mut buf_vec = vec![0; BUFFER_CAP];
-                        let bytes_read = match reader.read(&mut buf_vec).await {
-                            Ok(n) => match String::from_utf8(buf_vec[0..n].to_vec()) {
-                                Ok(data) => {
-                                    request.push_str(&data);
-                                    n
-                                }
-                                Err(e) => {
-                                    return Err(format!(
-                                        "Failed to decode request; err = {}",
-                                        e.to_string()
-                                    )
-                                    .into());
-                                }
-                            },
-                            Err(e) => {
-                                return Err(format!(
-                                    "Failed to read from socket; err = {}",
-                                    e.to_string()
-                                )
-                                .into());
-                            }
-                        };
-
-                        if bytes_read == 0 || bytes_read < BUFFER_CAP {
-                            break;
-                        }
-                    }
-
-                    let response = handle_message(request, clipboard_copy);
-
-                    if let Err(e) = writer.write_all(response.as_bytes()).await {
-                        return Err(e.to_string());
-                    }
-                }
-                Err(e) => {
-                    return Err(
-                        format!("Error with TLS negotiation; err = {}", e.to_string()).into(),
-                    );
-                }
-            }
-
-            Ok(())
-        });
+    if let Some(verifier) = client_cert_verifier {
+        builder = builder.client_cert_verifier(verifier);
     }
+
+    let backend = server_config
+        .clipboard
+        .backend
+        .unwrap_or_else(|| store::BACKEND_MEMORY.to_string());
+    let clipboard: Arc<dyn ClipboardStore> = store::new_store(&backend, server_config.clipboard.file.as_deref())?.into();
+
+    let handle = builder
+        .store(clipboard)
+        .tls_policy(server_config.tls.clone())
+        .max_size(server_config.clipboard.max_size.unwrap_or(rclip_server::DEFAULT_MAX_SIZE))
+        .rotation_policy(RotationPolicy::new(&server_config.rotation)?)
+        .activated_listeners(systemd::activated_listeners()?)
+        .verbose(true)
+        .spawn()
+        .await?;
+
+    systemd::notify_ready(&format!("Serving clipboard on {} listener(s)", handle.local_addrs().len()));
+
+    if let Some(interval) = systemd::watchdog_interval() {
+        systemd::spawn_watchdog(interval);
+    }
+
+    // Only returns when a listener fails
+    handle.wait().await
 }
 
-fn handle_message(data: String, clipboard: Arc<Mutex<String>>) -> String {
-    match clipboard.lock() {
-        Ok(mut clipboard_ref) => {
-            if data.starts_with(CMD_READ) {
-                return format!("SUCCESS:{}", clipboard_ref.as_str());
-            } else if data.starts_with(CMD_WRITE) {
-                let new_clipboard = &data[CMD_WRITE.len()..];
-                *clipboard_ref = new_clipboard.to_string();
-                return format!("SUCCESS:{}", new_clipboard);
-            } else if data.starts_with(CMD_CLEAR) {
-                clipboard_ref.clear();
-                return format!("SUCCESS:{}", EMPTY_CLIPBOARD_TEXT);
-            } else {
-                return format!("ERROR:Unknown message {}", data);
-            }
-        }
-        Err(ex) => {
-            return format!("ERROR:Could not acquire clipboard data. {}", ex);
+fn client_ca_verifier(auth: &rclip_config::ServerAuth) -> Result<Arc<dyn ClientCertVerifier>, Box<dyn Error>> {
+    let ca_cert_loc = match &auth.client_ca_file {
+        Some(ca_cert_loc) => ca_cert_loc.clone(),
+        None => ca::default_ca_cert_path()?.display().to_string(),
+    };
+
+    Ok(ca::client_cert_verifier(&ca_cert_loc)?)
+}
+
+// Serves the session of the standard input and output, started for a single connection. The clipboard
+// is kept in a file by default, and the standard output only carries the session.
+async fn serve_stdio(server_config: rclip_config::ServerConfig, tls: bool) -> Result<(), Box<dyn Error>> {
+    let backend = server_config
+        .clipboard
+        .backend
+        .unwrap_or_else(|| store::BACKEND_FILE.to_string());
+    let clipboard: Arc<dyn ClipboardStore> = store::new_store(&backend, server_config.clipboard.file.as_deref())?.into();
+
+    let mut builder = RclipServer::builder()
+        .store(clipboard)
+        .tls_policy(server_config.tls.clone())
+        .require_token(server_config.auth.require_token.unwrap_or(false))
+        .max_size(server_config.clipboard.max_size.unwrap_or(rclip_server::DEFAULT_MAX_SIZE));
+
+    if tls {
+        let key_pub_loc = server_config
+            .certificate
+            .der_cert_pub
+            .as_deref()
+            .ok_or("Please provide the public certificate argument for --der-cert-pub.")?;
+        let key_priv_loc = server_config
+            .certificate
+            .der_cert_priv
+            .as_deref()
+            .ok_or("Please provide the private certificate argument for --der-cert-priv.")?;
+
+        builder = builder.tls(ServerTls::from_files(key_pub_loc, key_priv_loc));
+
+        if server_config.auth.require_client_cert.unwrap_or(false) {
+            builder = builder.client_cert_verifier(client_ca_verifier(&server_config.auth)?);
         }
     }
+
+    builder.serve_stdio().await
 }
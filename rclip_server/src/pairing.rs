@@ -0,0 +1,177 @@
+// Pairing of new clients (`pair` subcommand): a one-time code lets a client fetch the server
+// certificate, and optionally a client token, without copying files around by hand.
+// The subcommand leaves a ticket in the data directory, which the running server consumes
+// on the first pairing request, whether it succeeds or not.
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use rand::RngCore;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rclip_config::pairing::{PairingCode, SECRET_LEN};
+
+pub const SUBCOMMAND_PAIR: &str = "pair";
+
+const FILENAME_PAIRING: &str = "pairing";
+const DEFAULT_TIMEOUT_MINUTES: &str = "10";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const STATE_PENDING: &str = "pending";
+const STATE_PAIRED: &str  = "paired";
+const STATE_FAILED: &str  = "failed";
+
+// Concurrent pairing requests must not both consume the ticket
+static TICKET_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name(SUBCOMMAND_PAIR)
+        .about("Prints a one-time code for pairing a client with the running server")
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .help("Validity period of the code in minutes")
+                .required(false)
+                .default_value(DEFAULT_TIMEOUT_MINUTES)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("issue-token")
+                .long("issue-token")
+                .help("Also issue a token to the client, for servers requiring authentication")
+                .required(false)
+                .takes_value(false),
+        )
+}
+
+pub fn run(matches: &ArgMatches, key_pub_loc: &str) -> Result<(), Box<dyn Error>> {
+    let minutes = matches
+        .value_of("timeout")
+        .unwrap_or(DEFAULT_TIMEOUT_MINUTES)
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid timeout. {}", e))?;
+
+    if minutes == 0 {
+        return Err("The timeout must be at least one minute.".into());
+    }
+
+    // Only the first certificate of a chain identifies the server
+    let cert_der = rclip_config::certs::load_certificates(key_pub_loc)?.remove(0);
+    let fingerprint = rclip_config::certs::fingerprint(&cert_der);
+
+    let mut secret = [0u8; SECRET_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+
+    let code = PairingCode::new(&fingerprint, &secret)?;
+    let expires = now() + minutes * 60;
+    let path = ticket_path()?;
+
+    write_ticket(&format!("{} {} {} {}", STATE_PENDING, code.secret, expires, matches.is_present("issue-token")))?;
+
+    println!("Pairing code: {}", code);
+    println!("On the client, run: rclip-client-cli pair --host <server-host> --code {}", code);
+    println!("The code is valid for {} minute(s) and a single attempt, the server must be running.", minutes);
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let ticket = fs::read_to_string(&path).unwrap_or_default();
+        let (state, detail) = ticket.trim().split_once(' ').unwrap_or((ticket.trim(), ""));
+
+        match state {
+            STATE_PAIRED => {
+                let _ = fs::remove_file(&path);
+                println!("Paired with client '{}'.", detail);
+                return Ok(());
+            }
+            STATE_FAILED => {
+                let _ = fs::remove_file(&path);
+                return Err(format!("Pairing failed: {}", detail).into());
+            }
+            STATE_PENDING if now() < expires => continue,
+            _ => {
+                let _ = fs::remove_file(&path);
+                return Err("The pairing code expired, no client attempted to pair.".into());
+            }
+        }
+    }
+}
+
+// Handles a pairing request, returning the server certificate and the optional client token.
+pub(crate) fn handle_pair(secret: &str, client_name: &str, cert_der: &[u8]) -> Result<String, String> {
+    let _lock = TICKET_LOCK
+        .lock()
+        .map_err(|e| format!("Could not acquire pairing ticket. {}", e))?;
+
+    let client_name = if client_name.is_empty() { "unnamed" } else { client_name };
+
+    let ticket = fs::read_to_string(ticket_path()?).map_err(|_| "No pairing in progress.".to_string())?;
+    let fields: Vec<&str> = ticket.split_whitespace().collect();
+
+    let (expected_secret, expires, issue_token) = match fields.as_slice() {
+        [STATE_PENDING, secret, expires, issue_token] => (
+            *secret,
+            expires.parse::<u64>().unwrap_or(0),
+            *issue_token == "true",
+        ),
+        _ => return Err("No pairing in progress.".to_string()),
+    };
+
+    if now() >= expires {
+        write_ticket(&format!("{} the code expired before client '{}' attempted to pair.", STATE_FAILED, client_name))?;
+        return Err("The pairing code expired.".to_string());
+    }
+
+    if ring::constant_time::verify_slices_are_equal(secret.as_bytes(), expected_secret.as_bytes()).is_err() {
+        write_ticket(&format!("{} client '{}' sent a wrong code.", STATE_FAILED, client_name))?;
+        return Err("Invalid pairing code, please request a new one.".to_string());
+    }
+
+    let token = if issue_token {
+        crate::auth::issue_token(client_name)?
+    } else {
+        String::new()
+    };
+
+    write_ticket(&format!("{} {}", STATE_PAIRED, client_name))?;
+
+    let cert_hex: String = cert_der.iter().map(|b| format!("{:02x}", b)).collect();
+
+    Ok(format!("certificate={}\ntoken={}", cert_hex, token))
+}
+
+fn ticket_path() -> Result<PathBuf, String> {
+    let data_dir = rclip_config::create_data_dir().map_err(|e| e.to_string())?;
+
+    Ok(data_dir.join(FILENAME_PAIRING))
+}
+
+fn write_ticket(contents: &str) -> Result<(), String> {
+    let path = ticket_path()?;
+    let mut options = fs::OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut f = options
+        .open(&path)
+        .map_err(|e| format!("Could not create '{}'. {}", path.display(), e))?;
+
+    f.write_all(contents.as_bytes())
+        .map_err(|e| format!("Could not write '{}'. {}", path.display(), e))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
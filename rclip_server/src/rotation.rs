@@ -0,0 +1,357 @@
+// Certificate expiry monitoring, with warnings as the end of the validity period gets close.
+// Self-managed certificates (the default files of the data directory, i.e. from `gen-cert`) can
+// also be rotated automatically: the next certificate is generated ahead of time and advertised
+// by the STATUS command, so that clients pinning fingerprints trust it before it gets served.
+//
+// Switching replaces the certificate and its key with two renames. A marker file records that the
+// switch started, so that a switch interrupted between the renames gets completed before the
+// certificate files are loaded again, instead of leaving a certificate that doesn't match its key.
+
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::identity::{Identity, IdentityResolver};
+
+pub const FILENAME_DER_CERT_NEXT_PUB: &str  = "der-cert-next-pub.der";
+pub const FILENAME_DER_CERT_NEXT_PRIV: &str = "der-cert-next-priv.der";
+const FILENAME_SWITCH_MARKER: &str          = "der-cert-switching";
+
+const DEFAULT_WARN_DAYS: i64     = 14;
+const DEFAULT_PREPARE_DAYS: i64  = 30;
+const DEFAULT_SWITCH_DAYS: i64   = 7;
+const DEFAULT_VALIDITY_DAYS: u32 = 365;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Copy)]
+pub struct RotationPolicy {
+    warn_days: i64,
+    auto_rotate: bool,
+    prepare_days: i64,
+    switch_days: i64,
+    validity_days: u32,
+}
+
+impl RotationPolicy {
+    pub fn new(config: &rclip_config::ServerRotation) -> Result<Self, String> {
+        let ret = Self {
+            warn_days: config.warn_days.map(i64::from).unwrap_or(DEFAULT_WARN_DAYS),
+            auto_rotate: config.auto_rotate.unwrap_or(false),
+            prepare_days: config.prepare_days.map(i64::from).unwrap_or(DEFAULT_PREPARE_DAYS),
+            switch_days: config.switch_days.map(i64::from).unwrap_or(DEFAULT_SWITCH_DAYS),
+            validity_days: config.validity_days.unwrap_or(DEFAULT_VALIDITY_DAYS),
+        };
+
+        if ret.auto_rotate && ret.prepare_days <= ret.switch_days {
+            return Err("The rotation prepare-days must be greater than switch-days, for clients to trust the next certificate in time.".to_string());
+        }
+
+        if ret.auto_rotate && i64::from(ret.validity_days) <= ret.prepare_days {
+            return Err("The rotation validity-days must be greater than prepare-days.".to_string());
+        }
+
+        Ok(ret)
+    }
+}
+
+// Checks the certificates right away, and then periodically.
+pub fn spawn_monitor(resolvers: Vec<Arc<IdentityResolver>>, policy: RotationPolicy) -> tokio::task::JoinHandle<()> {
+    let data_dir = rclip_config::create_data_dir().ok();
+
+    tokio::spawn(async move {
+        loop {
+            for resolver in &resolvers {
+                let resolver = resolver.clone();
+                let data_dir = data_dir.clone();
+
+                // Generating RSA keys takes a while
+                let _ = tokio::task::spawn_blocking(move || check(data_dir.as_deref(), &resolver, policy)).await;
+            }
+
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    })
+}
+
+// Completes a switch interrupted by a crash or a failed rename, before loading the certificate files.
+pub fn complete_interrupted_switch(key_pub_loc: &str, key_priv_loc: &str) -> Result<(), String> {
+    let data_dir = rclip_config::create_data_dir().map_err(|e| e.to_string())?;
+
+    match next_files(&data_dir, key_pub_loc, key_priv_loc) {
+        Some(next_files) if next_files.switch_marker.exists() => {
+            complete_switch(&next_files, key_pub_loc, key_priv_loc)?;
+            eprintln!("Completed the interrupted rotation of the certificate '{}'.", key_pub_loc);
+
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check(data_dir: Option<&Path>, resolver: &IdentityResolver, policy: RotationPolicy) {
+    let current = resolver.current();
+    let days = current.days_to_expiry();
+
+    if days < 0 {
+        eprintln!("ERROR: The certificate '{}' expired {} day(s) ago, clients cannot connect!", current.key_pub_loc, -days);
+    } else if days < policy.warn_days {
+        eprintln!("WARNING: The certificate '{}' expires in {} day(s).", current.key_pub_loc, days);
+    }
+
+    let next_files = data_dir.and_then(|data_dir| next_files(data_dir, &current.key_pub_loc, &current.key_priv_loc));
+
+    let next_files = match next_files {
+        Some(next_files) => next_files,
+        None => {
+            if policy.auto_rotate && days < policy.prepare_days {
+                eprintln!(
+                    "WARNING: The certificate '{}' isn't self-managed, it must be renewed manually.",
+                    current.key_pub_loc
+                );
+            }
+
+            return;
+        }
+    };
+
+    // A next certificate prepared before a restart is still advertised
+    if resolver.next().is_none() && next_files.cert.exists() && next_files.key.exists() {
+        match Identity::load(&next_files.cert.display().to_string(), &next_files.key.display().to_string()) {
+            Ok(next) => resolver.set_next(Some(next)),
+            Err(e) => eprintln!("Could not load the next certificate. {}", e),
+        }
+    }
+
+    if !policy.auto_rotate {
+        return;
+    }
+
+    if resolver.next().is_none() && days < policy.prepare_days {
+        if let Err(e) = prepare(resolver, &current, &next_files, policy) {
+            eprintln!("Could not prepare the next certificate of '{}'. {}", current.key_pub_loc, e);
+            return;
+        }
+    }
+
+    if resolver.next().is_some() && days < policy.switch_days {
+        if let Err(e) = switch(resolver, &current, &next_files) {
+            eprintln!("Could not rotate the certificate '{}'. {}", current.key_pub_loc, e);
+        }
+    }
+}
+
+struct NextFiles {
+    cert: PathBuf,
+    key: PathBuf,
+    // Present from the start of a switch until both files are replaced
+    switch_marker: PathBuf,
+}
+
+// Returns the paths of the next certificate and private key, for self-managed certificates only.
+fn next_files(data_dir: &Path, key_pub_loc: &str, key_priv_loc: &str) -> Option<NextFiles> {
+    let same_file = |loc: &str, filename: &str| {
+        let expected = data_dir.join(filename);
+        let actual = Path::new(loc);
+
+        actual == expected || matches!((actual.canonicalize(), expected.canonicalize()), (Ok(a), Ok(b)) if a == b)
+    };
+
+    if same_file(key_pub_loc, rclip_config::DEFAULT_FILENAME_DER_CERT_PUB)
+        && same_file(key_priv_loc, crate::FILENAME_DER_CERT_PRIV)
+    {
+        Some(NextFiles {
+            cert: data_dir.join(FILENAME_DER_CERT_NEXT_PUB),
+            key: data_dir.join(FILENAME_DER_CERT_NEXT_PRIV),
+            switch_marker: data_dir.join(FILENAME_SWITCH_MARKER),
+        })
+    } else {
+        None
+    }
+}
+
+// Generates the next certificate with the key type and names of the current one.
+fn prepare(
+    resolver: &IdentityResolver,
+    current: &Identity,
+    next_files: &NextFiles,
+    policy: RotationPolicy,
+) -> Result<(), String> {
+    use x509_parser::extensions::GeneralName;
+    use x509_parser::oid_registry::{OID_KEY_TYPE_EC_PUBLIC_KEY, OID_PKCS1_RSAENCRYPTION, OID_SIG_ED25519};
+
+    let (_, cert) = x509_parser::parse_x509_certificate(&current.certificate).map_err(|e| e.to_string())?;
+
+    let algorithm = &cert.public_key().algorithm.algorithm;
+    let (key_type, rsa_bits) = if *algorithm == OID_PKCS1_RSAENCRYPTION {
+        let bits = cert.public_key().parsed().map(|key| key.key_size()).unwrap_or(0);
+        (crate::certgen::KEY_TYPE_RSA, std::cmp::max(bits, 2048))
+    } else if *algorithm == OID_SIG_ED25519 {
+        (crate::certgen::KEY_TYPE_ED25519, 0)
+    } else if *algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        (crate::certgen::KEY_TYPE_ECDSA, 0)
+    } else {
+        return Err(format!("Unsupported key type {}.", algorithm));
+    };
+
+    let mut sans = Vec::new();
+
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns_name) => sans.push(dns_name.to_string()),
+                GeneralName::IPAddress(bytes) => {
+                    if let Ok(octets) = <[u8; 4]>::try_from(*bytes) {
+                        sans.push(IpAddr::from(octets).to_string());
+                    } else if let Ok(octets) = <[u8; 16]>::try_from(*bytes) {
+                        sans.push(IpAddr::from(octets).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let (cert_der, key_der) =
+        crate::certgen::generate(key_type, rsa_bits, &sans, policy.validity_days).map_err(|e| e.to_string())?;
+
+    crate::certgen::write_file(&next_files.cert, &cert_der, false).map_err(|e| e.to_string())?;
+    crate::certgen::write_file(&next_files.key, &key_der, true).map_err(|e| e.to_string())?;
+
+    let next = Identity::load(&next_files.cert.display().to_string(), &next_files.key.display().to_string())?;
+
+    println!(
+        "Prepared the next certificate of '{}', fingerprint: {}. It will be served {} day(s) before the current one expires.",
+        current.key_pub_loc, next.fingerprint, policy.switch_days
+    );
+
+    resolver.set_next(Some(next));
+
+    Ok(())
+}
+
+// Replaces the current certificate files with the next ones, and serves them.
+fn switch(resolver: &IdentityResolver, current: &Identity, next_files: &NextFiles) -> Result<(), String> {
+    crate::certgen::write_file(&next_files.switch_marker, b"", false).map_err(|e| e.to_string())?;
+    complete_switch(next_files, &current.key_pub_loc, &current.key_priv_loc)?;
+
+    let identity = Identity::load(&current.key_pub_loc, &current.key_priv_loc)?;
+
+    println!(
+        "Rotated the certificate '{}', fingerprint: {}.",
+        current.key_pub_loc, identity.fingerprint
+    );
+
+    resolver.promote_next(identity);
+
+    Ok(())
+}
+
+// Renames the next files that are left, i.e. both of them unless resuming an interrupted switch.
+fn complete_switch(next_files: &NextFiles, key_pub_loc: &str, key_priv_loc: &str) -> Result<(), String> {
+    for (next, loc) in [(&next_files.cert, key_pub_loc), (&next_files.key, key_priv_loc)] {
+        if next.exists() {
+            fs::rename(next, loc).map_err(|e| format!("Could not replace '{}'. {}", loc, e))?;
+        }
+    }
+
+    fs::remove_file(&next_files.switch_marker)
+        .map_err(|e| format!("Could not remove '{}'. {}", next_files.switch_marker.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation(auto_rotate: bool, prepare_days: u32, switch_days: u32, validity_days: u32) -> rclip_config::ServerRotation {
+        rclip_config::ServerRotation {
+            auto_rotate: Some(auto_rotate),
+            prepare_days: Some(prepare_days),
+            switch_days: Some(switch_days),
+            validity_days: Some(validity_days),
+            ..Default::default()
+        }
+    }
+
+    // Self-managed certificate files in a temporary data directory, expiring within a day
+    fn data_dir(name: &str) -> (PathBuf, String, String) {
+        let data_dir = std::env::temp_dir().join(format!("rclip-rotation-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        fs::create_dir_all(&data_dir).unwrap();
+
+        let sans = ["localhost".to_string()];
+        let (cert_der, key_der) = crate::certgen::generate(crate::certgen::KEY_TYPE_ECDSA, 0, &sans, 1).unwrap();
+        let key_pub_loc = data_dir.join(rclip_config::DEFAULT_FILENAME_DER_CERT_PUB);
+        let key_priv_loc = data_dir.join(crate::FILENAME_DER_CERT_PRIV);
+        fs::write(&key_pub_loc, cert_der).unwrap();
+        fs::write(&key_priv_loc, key_der).unwrap();
+
+        (data_dir, key_pub_loc.display().to_string(), key_priv_loc.display().to_string())
+    }
+
+    #[test]
+    fn validates_policies() {
+        assert!(RotationPolicy::new(&rclip_config::ServerRotation::default()).is_ok());
+        assert!(RotationPolicy::new(&rotation(true, 30, 7, 365)).is_ok());
+        assert!(RotationPolicy::new(&rotation(false, 7, 30, 1)).is_ok());
+
+        let err = RotationPolicy::new(&rotation(true, 7, 7, 365)).err().unwrap();
+        assert!(err.contains("prepare-days must be greater than switch-days"), "{}", err);
+
+        let err = RotationPolicy::new(&rotation(true, 30, 7, 30)).err().unwrap();
+        assert!(err.contains("validity-days must be greater than prepare-days"), "{}", err);
+    }
+
+    #[test]
+    fn rotates_expiring_certificates() {
+        let (data_dir, key_pub_loc, key_priv_loc) = data_dir("rotate");
+        let resolver = IdentityResolver::new(Identity::load(&key_pub_loc, &key_priv_loc).unwrap());
+        let old_fingerprint = resolver.current().fingerprint.clone();
+
+        // Prepared only, as the switch is still far away
+        check(Some(&data_dir), &resolver, RotationPolicy::new(&rotation(true, 30, 0, 365)).unwrap());
+        let next = resolver.next().unwrap();
+        let next_key = fs::read(data_dir.join(FILENAME_DER_CERT_NEXT_PRIV)).unwrap();
+        assert_ne!(next.fingerprint, old_fingerprint);
+        assert_eq!(resolver.current().fingerprint, old_fingerprint);
+        assert!(next.days_to_expiry() > 300);
+
+        check(Some(&data_dir), &resolver, RotationPolicy::new(&rotation(true, 30, 7, 365)).unwrap());
+        assert!(resolver.next().is_none());
+        assert_eq!(resolver.current().fingerprint, next.fingerprint);
+
+        let loaded = Identity::load(&key_pub_loc, &key_priv_loc).unwrap();
+        assert_eq!(loaded.fingerprint, next.fingerprint);
+        assert_eq!(fs::read(&key_priv_loc).unwrap(), next_key);
+
+        for filename in [FILENAME_DER_CERT_NEXT_PUB, FILENAME_DER_CERT_NEXT_PRIV, FILENAME_SWITCH_MARKER] {
+            assert!(!data_dir.join(filename).exists(), "{}", filename);
+        }
+
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn completes_interrupted_switches() {
+        let (data_dir, key_pub_loc, key_priv_loc) = data_dir("interrupted");
+        let resolver = IdentityResolver::new(Identity::load(&key_pub_loc, &key_priv_loc).unwrap());
+        check(Some(&data_dir), &resolver, RotationPolicy::new(&rotation(true, 30, 0, 365)).unwrap());
+
+        let next_files = next_files(&data_dir, &key_pub_loc, &key_priv_loc).unwrap();
+        let next_key = fs::read(&next_files.key).unwrap();
+        let next_fingerprint = resolver.next().unwrap().fingerprint.clone();
+
+        // Stopped right after replacing the certificate
+        fs::write(&next_files.switch_marker, b"").unwrap();
+        fs::rename(&next_files.cert, &key_pub_loc).unwrap();
+
+        complete_switch(&next_files, &key_pub_loc, &key_priv_loc).unwrap();
+        assert_eq!(fs::read(&key_priv_loc).unwrap(), next_key);
+        assert_eq!(Identity::load(&key_pub_loc, &key_priv_loc).unwrap().fingerprint, next_fingerprint);
+        assert!(!next_files.switch_marker.exists());
+
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+}
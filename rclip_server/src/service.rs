@@ -0,0 +1,317 @@
+// Serving of the connections: listeners, TLS sessions and the commands of each request.
+
+use bytes::Bytes;
+use std::error::Error;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{split, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{lookup_host, TcpListener};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::server::ClientCertVerifier;
+use tokio_rustls::TlsAcceptor;
+
+use crate::identity::IdentityResolver;
+use crate::store::ClipboardStore;
+use crate::transport::{self, Listener, Stream};
+use crate::{auth, framing, pairing};
+use rclip_protocol::{Command, ErrorCode, ErrorResponse, Request, Response, RESPONSE_SUCCESS};
+
+const DEVICE_REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+pub async fn bind(host: &str, port: u32, dual_stack: bool) -> Result<Listener, Box<dyn Error>> {
+    if let Some(path) = host.strip_prefix(rclip_config::UNIX_PREFIX) {
+        return transport::bind_unix(path)
+            .map_err(|e| format!("Could not bind to '{}': {}", host, e).into());
+    }
+
+    if let Some(cid) = rclip_config::parse_vsock_cid(host)? {
+        return transport::bind_vsock(cid, port).map_err(|e| {
+            format!("Could not bind to '{}': {}", rclip_config::format_address(host, port), e).into()
+        });
+    }
+
+    let host = rclip_config::unbracket_host(host);
+    let mut last_err: Option<io::Error> = None;
+
+    for addr in lookup_host((host, rclip_config::network_port(host, port)?)).await? {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+        // IPv6 sockets only accept IPv6 traffic unless dual-stack is requested,
+        // so that IPv4 and IPv6 wildcard addresses can both be bound on the same port.
+        if addr.is_ipv6() {
+            socket.set_only_v6(!dual_stack)?;
+        }
+
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+
+        let bound = socket
+            .bind(&addr.into())
+            .and_then(|_| socket.listen(1024))
+            .and_then(|_| socket.set_nonblocking(true));
+
+        match bound {
+            Ok(_) => return Ok(Listener::Tcp(TcpListener::from_std(socket.into())?)),
+            Err(e) => last_err = Some(e),
+        }
+    }
+
+    match last_err {
+        Some(e) => Err(format!("Could not bind to '{}': {}", rclip_config::format_address(host, port), e).into()),
+        None => Err(format!("Could not resolve any address for '{}'!", host).into()),
+    }
+}
+
+// Everything needed to serve the connections of a listener. Only plain text sessions of --stdio --no-tls
+// go without TLS, and therefore without a server identity.
+#[derive(Clone)]
+pub struct Service {
+    pub acceptor: Option<TlsAcceptor>,
+    pub clipboard: Arc<dyn ClipboardStore>,
+    pub identity: Option<Arc<IdentityResolver>>,
+    pub require_token: bool,
+    pub max_size: usize,
+}
+
+pub fn new_service(
+    identity: Arc<IdentityResolver>,
+    client_cert_verifier: Option<Arc<dyn ClientCertVerifier>>,
+    clipboard: &Arc<dyn ClipboardStore>,
+    tls_policy: &rclip_config::tls::TlsPolicy,
+    require_token: bool,
+    max_size: usize,
+) -> Result<Service, Box<dyn Error>> {
+    let builder = rclip_config::tls::apply(rustls::ServerConfig::builder(), tls_policy)?;
+    let require_client_cert = client_cert_verifier.is_some();
+    let builder = match client_cert_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(identity.clone());
+
+    if require_client_cert {
+        // Resumed sessions skip the verification of client certificates, which would keep revoked ones working
+        config.session_storage = Arc::new(rustls::server::NoServerSessionStorage {});
+    } else {
+        // Stateless session tickets, so that clients resume their previous session instead of a full handshake
+        config.ticketer = rustls::Ticketer::new()?;
+    }
+
+    Ok(Service {
+        acceptor: Some(TlsAcceptor::from(Arc::new(config))),
+        clipboard: clipboard.clone(),
+        identity: Some(identity),
+        require_token,
+        max_size,
+    })
+}
+
+pub async fn accept_loop(listener: Listener, service: Service) -> io::Result<()> {
+    loop {
+        let stream = listener.accept().await?;
+        let service_copy = service.clone();
+
+        if listener.is_framed() {
+            tokio::spawn(serve_channel(stream, service_copy));
+        } else {
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, service_copy).await {
+                    eprintln!("{}", e);
+                }
+            });
+        }
+    }
+}
+
+// Serves the successive sessions of a persistent channel (character device or its Unix socket end).
+async fn serve_channel<S: Stream>(channel: S, service: Service) -> io::Result<()> {
+    let ret = framing::serve_channel(channel, |session_io| {
+        let service = service.clone();
+
+        async move {
+            if let Err(e) = handle_connection(session_io, service).await {
+                eprintln!("{}", e);
+            }
+        }
+    })
+    .await;
+
+    // A closed channel is the normal outcome when the other end goes away
+    if let Err(e) = &ret {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset => {}
+            _ => eprintln!("Channel failure; err = {}", e),
+        }
+    }
+
+    ret
+}
+
+// Keeps serving a character device, reopening it whenever the other end goes away.
+pub async fn serve_device(device: transport::Device, service: Service) -> io::Result<()> {
+    loop {
+        let channel = device.open()?;
+        let _ = serve_channel(channel, service.clone()).await;
+        tokio::time::sleep(DEVICE_REOPEN_DELAY).await;
+    }
+}
+
+pub async fn handle_connection<S: Stream>(stream: S, service: Service) -> Result<(), String> {
+    match &service.acceptor {
+        Some(acceptor) => match acceptor.accept(stream).await {
+            Ok(stream) => handle_session(stream, &service).await,
+            Err(e) => Err(format!("Error with TLS negotiation; err = {}", e)),
+        },
+        None => handle_session(stream, &service).await,
+    }
+}
+
+async fn handle_session<S: Stream>(stream: S, service: &Service) -> Result<(), String> {
+    let (reader, mut writer) = split(stream);
+
+    let request = read_request(reader, service.max_size)
+        .await
+        .map_err(|e| format!("Failed to read from socket; err = {}", e))?;
+
+    let response = request.and_then(|request| match String::from_utf8(request) {
+        Ok(request) => handle_message(request, service),
+        Err(e) => Err(ErrorResponse::new(ErrorCode::Protocol, format!("Failed to decode request. {}", e))),
+    });
+
+    // The body is written from the buffer shared with the clipboard store, instead of a copy into the response
+    let written = match response {
+        Ok(body) => match writer.write_all(RESPONSE_SUCCESS.as_bytes()).await {
+            Ok(_) => writer.write_all(&body).await,
+            Err(e) => Err(e),
+        },
+        Err(error) => writer.write_all(Response::Error(error).to_string().as_bytes()).await,
+    };
+
+    if let Err(e) = written {
+        return Err(e.to_string());
+    }
+
+    // Sends the TLS close_notify alert, which is the only end of response marker on framed channels
+    if let Err(e) = writer.shutdown().await {
+        return Err(e.to_string());
+    }
+
+    Ok(())
+}
+
+// Reads the length header, then the announced bytes however the transport splits them. Requests cut
+// short by the end of the input are rejected rather than executed.
+async fn read_request<R: AsyncRead + Unpin>(reader: R, max_size: usize) -> io::Result<Result<Vec<u8>, ErrorResponse>> {
+    let mut reader = BufReader::new(reader);
+    let mut header = Vec::new();
+    (&mut reader)
+        .take(rclip_protocol::MAX_HEADER_LEN as u64)
+        .read_until(b'\n', &mut header)
+        .await?;
+
+    let len = match rclip_protocol::decode_length_header(&header) {
+        Ok(len) => len,
+        Err(e) => return Ok(Err(ErrorResponse::new(ErrorCode::Protocol, e.to_string()))),
+    };
+
+    // The rest of a request that is too large is still read, so that the client gets the response
+    if len > max_size {
+        tokio::io::copy(&mut (&mut reader).take(len as u64), &mut tokio::io::sink()).await?;
+
+        return Ok(Err(ErrorResponse::new(
+            ErrorCode::Limit,
+            format!("The request exceeds the maximum size of {} bytes.", max_size),
+        )));
+    }
+
+    let mut request = Vec::with_capacity(len);
+
+    // TLS connections closed without close_notify end with an error instead
+    match (&mut reader).take(len as u64).read_to_end(&mut request).await {
+        Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => return Err(e),
+        _ => {}
+    }
+
+    if request.len() < len {
+        return Ok(Err(ErrorResponse::new(
+            ErrorCode::Protocol,
+            format!("The request was truncated after {} of {} bytes.", request.len(), len),
+        )));
+    }
+
+    Ok(Ok(request))
+}
+
+fn handle_message(request: String, service: &Service) -> Result<Bytes, ErrorResponse> {
+    match Request::decode(&request) {
+        Ok(request) => execute(request, service),
+        Err(e) => Err(ErrorResponse::new(ErrorCode::Protocol, e.to_string())),
+    }
+}
+
+// Writes are acknowledged with an empty body, the text being already known to the client.
+fn execute(request: Request, service: &Service) -> Result<Bytes, ErrorResponse> {
+    let clipboard = &service.clipboard;
+    let internal_error = |e: String| ErrorResponse::new(ErrorCode::Internal, e);
+
+    if !matches!(request.command, Command::Pair { .. }) {
+        authorize(request.token.as_deref(), service.require_token)?;
+    }
+
+    match request.command {
+        Command::Pair { secret, client_name } => match &service.identity {
+            Some(identity) => pairing::handle_pair(&secret, &client_name, &identity.current().certificate)
+                .map(Bytes::from)
+                .map_err(|e| ErrorResponse::new(ErrorCode::Auth, e)),
+            None => Err(ErrorResponse::new(ErrorCode::Protocol, "Pairing requires TLS.")),
+        },
+        Command::Read => clipboard.read().map_err(internal_error),
+        Command::Write(new_clipboard) => clipboard
+            .write(Bytes::from(new_clipboard))
+            .map(|_| Bytes::new())
+            .map_err(internal_error),
+        Command::Status => match &service.identity {
+            Some(identity) => Ok(Bytes::from(status(identity))),
+            None => Ok(Bytes::from(format!("version={}", option_env!("CARGO_PKG_VERSION").unwrap_or("Unknown")))),
+        },
+        Command::Clear => clipboard.clear().map(|_| Bytes::new()).map_err(internal_error),
+    }
+}
+
+// One "key=value" line per property, the next certificate being only known once a rotation is prepared.
+fn status(identity: &IdentityResolver) -> String {
+    let current = identity.current();
+    let mut ret = format!(
+        "version={}\nfingerprint={}\ndays-to-expiry={}",
+        option_env!("CARGO_PKG_VERSION").unwrap_or("Unknown"),
+        current.fingerprint,
+        current.days_to_expiry()
+    );
+
+    if let Some(next) = identity.next() {
+        ret.push_str(&format!("\nnext-fingerprint={}", next.fingerprint));
+    }
+
+    ret
+}
+
+// Pairing requests are the only ones accepted without a token, once tokens are required.
+fn authorize(token: Option<&str>, require_token: bool) -> Result<(), ErrorResponse> {
+    if !require_token {
+        return Ok(());
+    }
+
+    let known_token = match token {
+        Some(token) => auth::is_known_token(token).map_err(|e| ErrorResponse::new(ErrorCode::Internal, e))?,
+        None => return Err(ErrorResponse::new(ErrorCode::Auth, "A client token is required, please pair this client with the server.")),
+    };
+
+    if known_token {
+        Ok(())
+    } else {
+        Err(ErrorResponse::new(ErrorCode::Auth, "Unknown client token, please pair this client again."))
+    }
+}
@@ -0,0 +1,245 @@
+// Clipboard storage backends: a private in-memory text (default), a text file or, with the
+// `system-clipboard` feature, the clipboard of the desktop session running the server.
+//
+// The contents are immutable shared buffers, so that concurrent reads of a large clipboard only
+// clone a reference instead of the text, and changes are published to subscribers.
+
+use bytes::Bytes;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tokio::sync::watch;
+
+pub const BACKEND_MEMORY: &str = "memory";
+pub const BACKEND_SYSTEM: &str = "system";
+pub const BACKEND_FILE: &str = "file";
+
+pub const FILENAME_CLIPBOARD: &str = "clipboard";
+
+// Contents are UTF-8 text.
+pub trait ClipboardStore: Send + Sync {
+    fn read(&self) -> Result<Bytes, String>;
+    fn write(&self, text: Bytes) -> Result<(), String>;
+
+    fn clear(&self) -> Result<(), String> {
+        self.write(Bytes::new())
+    }
+
+    // Receives the contents after each change made through this store. Changes made outside the
+    // server, to the file of a FileStore or by the desktop applications of a SystemStore, are missed:
+    // poll read() to notice them.
+    fn subscribe(&self) -> watch::Receiver<Bytes>;
+}
+
+// Readers only hold the lock of the channel while cloning the reference to the current contents.
+pub struct MemoryStore {
+    text: watch::Sender<Bytes>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self {
+            text: watch::channel(Bytes::new()).0,
+        }
+    }
+}
+
+impl ClipboardStore for MemoryStore {
+    fn read(&self) -> Result<Bytes, String> {
+        Ok(self.text.borrow().clone())
+    }
+
+    fn write(&self, text: Bytes) -> Result<(), String> {
+        self.text.send_replace(text);
+
+        Ok(())
+    }
+
+    fn subscribe(&self) -> watch::Receiver<Bytes> {
+        self.text.subscribe()
+    }
+}
+
+// Outlives the server process, i.e. when a process is started for each connection with --stdio.
+// Writes replace the file at once, as several processes may share it. The file is only read when the
+// store is created, the contents then being served from memory like with a MemoryStore.
+pub struct FileStore {
+    path: PathBuf,
+    // Changes of other processes sharing the file are neither read nor published
+    changes: watch::Sender<Bytes>,
+}
+
+impl FileStore {
+    pub fn new(path: Option<&str>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => rclip_config::create_data_dir()
+                .map_err(|e| e.to_string())?
+                .join(FILENAME_CLIPBOARD),
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => Bytes::from(text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Bytes::new(),
+            Err(e) => return Err(format!("Could not read the clipboard file '{}'. {}", path.display(), e)),
+        };
+
+        Ok(Self {
+            path,
+            changes: watch::channel(text).0,
+        })
+    }
+
+    fn write_file(&self, text: &[u8]) -> io::Result<()> {
+        let tmp_path = self.path.with_extension(format!("tmp{}", std::process::id()));
+        let mut options = fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+
+        // The clipboard may hold passwords
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut f = options.open(&tmp_path)?;
+        f.write_all(text)?;
+        drop(f);
+
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+impl ClipboardStore for FileStore {
+    fn read(&self) -> Result<Bytes, String> {
+        Ok(self.changes.borrow().clone())
+    }
+
+    fn write(&self, text: Bytes) -> Result<(), String> {
+        self.write_file(&text)
+            .map_err(|e| format!("Could not write the clipboard file '{}'. {}", self.path.display(), e))?;
+        self.changes.send_replace(text);
+
+        Ok(())
+    }
+
+    fn subscribe(&self) -> watch::Receiver<Bytes> {
+        self.changes.subscribe()
+    }
+}
+
+// Uses copypasta, the clipboard library behind the desktop client. Unlike the client, which exits
+// right away and therefore forks to keep X11 selections alive, the server owns a single context
+// for its whole lifetime. The context lives on a dedicated thread, as some platforms don't allow
+// sharing it between threads.
+#[cfg(feature = "system-clipboard")]
+pub struct SystemStore {
+    requests: std::sync::Mutex<std::sync::mpsc::Sender<SystemRequest>>,
+    // Copies made by other applications of the desktop aren't published
+    changes: watch::Sender<Bytes>,
+}
+
+#[cfg(feature = "system-clipboard")]
+type SystemRequest = (Option<String>, std::sync::mpsc::Sender<Result<String, String>>);
+
+#[cfg(feature = "system-clipboard")]
+impl SystemStore {
+    pub fn new() -> Result<Self, String> {
+        use copypasta::ClipboardProvider;
+        use std::sync::mpsc;
+
+        let (requests_tx, requests_rx) = mpsc::channel::<SystemRequest>();
+        let (init_tx, init_rx) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut ctx = match copypasta::ClipboardContext::new() {
+                Ok(ctx) => {
+                    let _ = init_tx.send(Ok(()));
+                    ctx
+                }
+                Err(e) => {
+                    let _ = init_tx.send(Err(format!("Could not access the system clipboard. {}", e)));
+                    return;
+                }
+            };
+
+            // A request without text is a read, otherwise the text replaces the clipboard contents
+            for (text, reply_tx) in requests_rx {
+                let ret = match text {
+                    // Exception under Windows when the clipboard is empty.
+                    None => Ok(ctx.get_contents().unwrap_or_default()),
+                    Some(text) => ctx
+                        .set_contents(text)
+                        .map(|_| String::new())
+                        .map_err(|e| e.to_string()),
+                };
+
+                let _ = reply_tx.send(ret);
+            }
+        });
+
+        init_rx
+            .recv()
+            .map_err(|e| format!("Could not access the system clipboard. {}", e))??;
+
+        Ok(Self {
+            requests: std::sync::Mutex::new(requests_tx),
+            changes: watch::channel(Bytes::new()).0,
+        })
+    }
+
+    fn request(&self, text: Option<String>) -> Result<String, String> {
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+
+        self.requests
+            .lock()
+            .map_err(|e| format!("Could not acquire clipboard data. {}", e))?
+            .send((text, reply_tx))
+            .map_err(|e| format!("Could not acquire clipboard data. {}", e))?;
+
+        reply_rx
+            .recv()
+            .map_err(|e| format!("Could not acquire clipboard data. {}", e))?
+    }
+}
+
+#[cfg(feature = "system-clipboard")]
+impl ClipboardStore for SystemStore {
+    fn read(&self) -> Result<Bytes, String> {
+        self.request(None).map(Bytes::from)
+    }
+
+    fn write(&self, text: Bytes) -> Result<(), String> {
+        let mut clipboard_text = std::str::from_utf8(&text)
+            .map_err(|e| format!("Invalid clipboard text. {}", e))?
+            .to_string();
+
+        if clipboard_text.is_empty() && cfg!(target_os = "windows") {
+            clipboard_text.push('\0'); // same workaround as the client
+        }
+
+        self.request(Some(clipboard_text))?;
+        self.changes.send_replace(text);
+
+        Ok(())
+    }
+
+    fn subscribe(&self) -> watch::Receiver<Bytes> {
+        self.changes.subscribe()
+    }
+}
+
+pub fn new_store(backend: &str, file: Option<&str>) -> Result<Box<dyn ClipboardStore>, String> {
+    match backend {
+        BACKEND_MEMORY => Ok(Box::<MemoryStore>::default()),
+        BACKEND_FILE => Ok(Box::new(FileStore::new(file)?)),
+        #[cfg(feature = "system-clipboard")]
+        BACKEND_SYSTEM => Ok(Box::new(SystemStore::new()?)),
+        #[cfg(not(feature = "system-clipboard"))]
+        BACKEND_SYSTEM => Err(format!(
+            "The '{}' clipboard backend requires a build with the 'system-clipboard' feature.",
+            BACKEND_SYSTEM
+        )),
+        _ => Err(format!("Unknown clipboard backend '{}'.", backend)),
+    }
+}
@@ -0,0 +1,90 @@
+// systemd integration: socket activation (LISTEN_FDS) and readiness/watchdog
+// notifications (NOTIFY_SOCKET). Everything is a no-op when not started by systemd.
+
+use socket2::Socket;
+#[cfg(unix)]
+use socket2::Type;
+use std::io;
+use std::time::Duration;
+
+// Sockets passed by systemd, whatever their address family (ListenStream= with an IP address, a path
+// or a vsock address). Datagram sockets, FIFOs and other files can't be served.
+#[cfg(unix)]
+pub fn activated_listeners() -> io::Result<Vec<Socket>> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut listeners = Vec::new();
+
+    for fd in sd_notify::listen_fds()? {
+        // systemd passes ownership of the descriptors to the activated process
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+
+        match socket.r#type() {
+            Ok(socket_type) if socket_type == Type::STREAM => listeners.push(socket),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The file descriptor {} passed by systemd isn't a stream socket, only ListenStream= is supported.", fd),
+                ))
+            }
+        }
+    }
+
+    Ok(listeners)
+}
+
+#[cfg(not(unix))]
+pub fn activated_listeners() -> io::Result<Vec<Socket>> {
+    Ok(Vec::new())
+}
+
+#[cfg(unix)]
+pub fn notify_ready(status: &str) {
+    use sd_notify::NotifyState;
+
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready, NotifyState::Status(status)]) {
+        eprintln!("Warn: Could not send readiness notification: {}!", e);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify_ready(_status: &str) {}
+
+// Returns the interval at which watchdog pings must be sent, if the watchdog is enabled.
+#[cfg(unix)]
+pub fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+
+    if sd_notify::watchdog_enabled(false, &mut usec) && usec > 0 {
+        // Ping twice per watchdog period, as recommended by sd_watchdog_enabled(3)
+        Some(Duration::from_micros(usec / 2))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn watchdog_interval() -> Option<Duration> {
+    None
+}
+
+pub fn spawn_watchdog(interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+            notify_watchdog();
+        }
+    });
+}
+
+#[cfg(unix)]
+fn notify_watchdog() {
+    if let Err(e) = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]) {
+        eprintln!("Warn: Could not send watchdog notification: {}!", e);
+    }
+}
+
+#[cfg(not(unix))]
+fn notify_watchdog() {}
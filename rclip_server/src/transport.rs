@@ -0,0 +1,406 @@
+// Transports carrying the TLS sessions: TCP sockets, AF_VSOCK sockets under Linux, as well as
+// character devices and Unix sockets for host/guest communication without any network configuration.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpListener;
+
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(target_os = "linux")]
+    Vsock(vsock::VsockListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+pub enum LocalAddr {
+    Tcp(std::net::SocketAddr),
+    Device(String),
+    #[cfg(target_os = "linux")]
+    Vsock(u32, u32),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+impl fmt::Display for LocalAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LocalAddr::Tcp(addr) => write!(f, "{}", addr),
+            LocalAddr::Device(path) => write!(f, "{}{}", rclip_config::DEVICE_PREFIX, path),
+            #[cfg(target_os = "linux")]
+            LocalAddr::Vsock(cid, port) => write!(f, "{}{}:{}", rclip_config::VSOCK_PREFIX, cid, port),
+            #[cfg(unix)]
+            LocalAddr::Unix(path) => write!(f, "{}{}", rclip_config::UNIX_PREFIX, path),
+        }
+    }
+}
+
+impl Listener {
+    pub async fn accept(&self) -> io::Result<Box<dyn Stream>> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(target_os = "linux")]
+            Listener::Vsock(listener) => Ok(Box::new(listener.accept().await?)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+
+    // Listening socket passed by a service manager, its address family picking the transport.
+    pub fn from_socket(socket: socket2::Socket) -> io::Result<Self> {
+        let addr = socket.local_addr()?;
+        socket.set_nonblocking(true)?;
+
+        if addr.as_socket().is_some() {
+            return Ok(Listener::Tcp(TcpListener::from_std(socket.into())?));
+        }
+
+        #[cfg(unix)]
+        if addr.is_unix() {
+            return Ok(Listener::Unix(tokio::net::UnixListener::from_std(socket.into())?));
+        }
+
+        #[cfg(target_os = "linux")]
+        if addr.as_vsock_address().is_some() {
+            return Ok(Listener::Vsock(vsock::VsockListener::from_socket(socket)?));
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Unsupported address family {} of a passed socket", addr.family()),
+        ))
+    }
+
+    // Unix sockets are meant for character devices exposed by hypervisors (i.e. QEMU chardev),
+    // which keep a single connection open across all the sessions.
+    pub fn is_framed(&self) -> bool {
+        match self {
+            #[cfg(unix)]
+            Listener::Unix(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<LocalAddr> {
+        match self {
+            Listener::Tcp(listener) => Ok(LocalAddr::Tcp(listener.local_addr()?)),
+            #[cfg(target_os = "linux")]
+            Listener::Vsock(listener) => {
+                let (cid, port) = listener.local_addr()?;
+                Ok(LocalAddr::Vsock(cid, port))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let path = listener
+                    .local_addr()?
+                    .as_pathname()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default();
+                Ok(LocalAddr::Unix(path))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn bind_unix(path: &str) -> io::Result<Listener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Remove any socket left behind by a previous run
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?))
+}
+
+#[cfg(not(unix))]
+pub fn bind_unix(_path: &str) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix socket addresses are only supported under Unix systems",
+    ))
+}
+
+// Standard input and output of the process, for a single session started by inetd, socat or SSH.
+// Unlike with tokio::io::Stdout, whose writes go through the line buffer of the standard library
+// until the next newline or flush, each write is handed over to the operating system.
+pub struct Stdio {
+    stdin: tokio::fs::File,
+    stdout: tokio::fs::File,
+}
+
+impl Stdio {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            stdin: tokio::fs::File::from_std(duplicate(&std::io::stdin())?),
+            stdout: tokio::fs::File::from_std(duplicate(&std::io::stdout())?),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn duplicate<T: std::os::unix::io::AsFd>(stdio: &T) -> io::Result<std::fs::File> {
+    Ok(stdio.as_fd().try_clone_to_owned()?.into())
+}
+
+#[cfg(windows)]
+fn duplicate<T: std::os::windows::io::AsHandle>(stdio: &T) -> io::Result<std::fs::File> {
+    Ok(stdio.as_handle().try_clone_to_owned()?.into())
+}
+
+impl AsyncRead for Stdio {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Stdio {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stdout).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_shutdown(cx)
+    }
+}
+
+// Character device such as a virtio-serial port (/dev/virtio-ports/...), a serial line or a pty.
+pub struct Device {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    path: String,
+}
+
+impl Device {
+    pub fn new(path: &str) -> io::Result<Self> {
+        if cfg!(not(unix)) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Device addresses are only supported under Unix systems",
+            ));
+        }
+
+        if !std::path::Path::new(path).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("The device doesn't exists at '{}'", path),
+            ));
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+        })
+    }
+
+    #[cfg(unix)]
+    pub fn open(&self) -> io::Result<fd::AsyncFdStream<std::fs::File>> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+            .open(&self.path)?;
+
+        fd::make_raw(&file)?;
+        fd::AsyncFdStream::new(file)
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(&self) -> io::Result<tokio::io::DuplexStream> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Device addresses are only supported under Unix systems",
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn bind_vsock(cid: u32, port: u32) -> io::Result<Listener> {
+    Ok(Listener::Vsock(vsock::VsockListener::bind(cid, port)?))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_vsock(_cid: u32, _port: u32) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "vsock addresses are only supported under Linux",
+    ))
+}
+
+#[cfg(unix)]
+pub mod fd {
+    use std::io::{self, Read, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::pin::Pin;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    // Disables the line discipline of terminals (echo, newline translation, etc.), so that
+    // pseudo-terminals and serial lines carry bytes unchanged. Other files are left untouched.
+    pub fn make_raw<T: AsRawFd>(file: &T) -> io::Result<()> {
+        let fd = file.as_raw_fd();
+
+        unsafe {
+            if libc::isatty(fd) != 1 {
+                return Ok(());
+            }
+
+            let mut termios = std::mem::zeroed::<libc::termios>();
+
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            libc::cfmakeraw(&mut termios);
+
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Non-blocking file descriptor (socket or character device) driven by the tokio reactor.
+    pub struct AsyncFdStream<T: AsRawFd> {
+        inner: AsyncFd<T>,
+    }
+
+    impl<T: AsRawFd> AsyncFdStream<T> {
+        // The descriptor must already be in non-blocking mode.
+        pub fn new(inner: T) -> io::Result<Self> {
+            Ok(Self {
+                inner: AsyncFd::new(inner)?,
+            })
+        }
+    }
+
+    impl<T: AsRawFd> AsyncRead for AsyncFdStream<T>
+    where
+        for<'a> &'a T: Read,
+    {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            loop {
+                let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+                let unfilled = buf.initialize_unfilled();
+
+                match guard.try_io(|inner| inner.get_ref().read(unfilled)) {
+                    Ok(Ok(n)) => {
+                        buf.advance(n);
+                        return Poll::Ready(Ok(()));
+                    }
+                    Ok(Err(e)) => return Poll::Ready(Err(e)),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+    }
+
+    impl<T: AsRawFd> AsyncWrite for AsyncFdStream<T>
+    where
+        for<'a> &'a T: Write,
+    {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            loop {
+                let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+
+                match guard.try_io(|inner| inner.get_ref().write(buf)) {
+                    Ok(result) => return Poll::Ready(result),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub mod vsock {
+    use super::fd::AsyncFdStream;
+    use socket2::{Domain, SockAddr, Socket, Type};
+    use std::io;
+    use tokio::io::unix::AsyncFd;
+
+    pub type VsockStream = AsyncFdStream<Socket>;
+
+    pub struct VsockListener {
+        inner: AsyncFd<Socket>,
+    }
+
+    impl VsockListener {
+        pub fn bind(cid: u32, port: u32) -> io::Result<Self> {
+            let socket = Socket::new(Domain::VSOCK, Type::STREAM, None)?;
+            socket.bind(&SockAddr::vsock(cid, port))?;
+            socket.listen(1024)?;
+            socket.set_nonblocking(true)?;
+
+            Self::from_socket(socket)
+        }
+
+        // The socket must already be listening, in non-blocking mode.
+        pub fn from_socket(socket: Socket) -> io::Result<Self> {
+            Ok(Self {
+                inner: AsyncFd::new(socket)?,
+            })
+        }
+
+        pub async fn accept(&self) -> io::Result<VsockStream> {
+            loop {
+                let mut guard = self.inner.readable().await?;
+
+                match guard.try_io(|inner| inner.get_ref().accept()) {
+                    Ok(Ok((socket, _))) => {
+                        socket.set_nonblocking(true)?;
+                        return AsyncFdStream::new(socket);
+                    }
+                    Ok(Err(e)) => return Err(e),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+
+        pub fn local_addr(&self) -> io::Result<(u32, u32)> {
+            self.inner
+                .get_ref()
+                .local_addr()?
+                .as_vsock_address()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not a vsock address"))
+        }
+    }
+}
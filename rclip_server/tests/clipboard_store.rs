@@ -0,0 +1,102 @@
+use rclip_client_lib::{Command, RclipClient};
+use rclip_server::{certgen, Bytes, ClipboardStore, FileStore, ListenerOptions, LocalAddr, RclipServer, ServerHandle, ServerTls};
+
+const MAX_SIZE: usize = 4 * 1024 * 1024;
+const READERS: usize = 8;
+
+async fn spawn_server() -> (ServerHandle, RclipClient) {
+    // The client keeps its TLS sessions in the data directory
+    let data_dir = std::env::temp_dir().join(format!("rclip-server-test-store-{}", std::process::id()));
+    std::env::set_var("XDG_DATA_HOME", &data_dir);
+
+    let sans = ["127.0.0.1".to_string()];
+    let (cert_der, key_der) = certgen::generate(certgen::KEY_TYPE_ECDSA, 0, &sans, 1).unwrap();
+    let fingerprint = rclip_config::certs::fingerprint(&cert_der);
+
+    let server = RclipServer::builder()
+        .tls(ServerTls::from_der(vec![cert_der], key_der))
+        .listener(ListenerOptions::new("127.0.0.1", 0))
+        .max_size(MAX_SIZE)
+        .spawn()
+        .await
+        .unwrap();
+
+    let port = match server.local_addrs() {
+        [LocalAddr::Tcp(addr)] => addr.port(),
+        _ => panic!("Expected a single TCP listener."),
+    };
+
+    let mut config = rclip_config::ClientConfig::default();
+    config.server.host = Some("127.0.0.1".to_string());
+    config.server.port = Some(port.into());
+    config.certificate.fingerprints = Some(vec![fingerprint]);
+
+    (server, RclipClient::connect(config).unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_concurrent_readers() {
+    let (server, client) = spawn_server().await;
+    let text = "0123456789abcdef".repeat(MAX_SIZE / 32);
+
+    let client_copy = client.clone();
+    let text_copy = text.clone();
+    tokio::task::spawn_blocking(move || client_copy.write(&text_copy)).await.unwrap().unwrap();
+
+    let readers: Vec<_> = (0..READERS)
+        .map(|_| {
+            let client = client.clone();
+            tokio::task::spawn_blocking(move || client.read())
+        })
+        .collect();
+
+    for reader in readers {
+        assert!(reader.await.unwrap().unwrap() == text);
+    }
+
+    // Readers share the stored buffer
+    let first = server.clipboard().read().unwrap();
+    let second = server.clipboard().read().unwrap();
+    assert_eq!(first.as_ptr(), second.as_ptr());
+
+    server.stop();
+    server.wait().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn acknowledges_writes_without_echo() {
+    let (server, client) = spawn_server().await;
+
+    let body = tokio::task::spawn_blocking(move || client.send(&Command::Write("some text".to_string())))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(body, "");
+    assert_eq!(&server.clipboard().read().unwrap()[..], b"some text");
+
+    server.stop();
+    server.wait().await.unwrap();
+}
+
+#[test]
+fn file_store_reads_the_file_once() {
+    let path = std::env::temp_dir().join(format!("rclip-server-test-clipboard-{}", std::process::id()));
+    std::fs::write(&path, "before").unwrap();
+
+    let store = FileStore::new(path.to_str()).unwrap();
+    let mut changes = store.subscribe();
+    assert_eq!(&store.read().unwrap()[..], b"before");
+
+    // Changes of other processes are missed
+    std::fs::write(&path, "elsewhere").unwrap();
+    assert_eq!(&store.read().unwrap()[..], b"before");
+    assert!(!changes.has_changed().unwrap());
+
+    store.write(Bytes::from_static(b"after")).unwrap();
+    assert_eq!(&store.read().unwrap()[..], b"after");
+    assert_eq!(&changes.borrow_and_update()[..], b"after");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "after");
+
+    let _ = std::fs::remove_file(&path);
+}
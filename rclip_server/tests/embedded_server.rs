@@ -0,0 +1,102 @@
+use rclip_client_lib::{ClientError, RclipClient};
+use rclip_server::{certgen, ListenerOptions, LocalAddr, RclipServer, ServerTls};
+
+const MAX_SIZE: usize = 64;
+
+fn client_config(port: u16, fingerprint: &str) -> rclip_config::ClientConfig {
+    let mut ret = rclip_config::ClientConfig::default();
+
+    ret.server.host = Some("127.0.0.1".to_string());
+    ret.server.port = Some(port.into());
+    ret.certificate.fingerprints = Some(vec![fingerprint.to_string()]);
+
+    ret
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_clients_until_stopped() {
+    // The client keeps its TLS sessions in the data directory
+    let data_dir = std::env::temp_dir().join(format!("rclip-server-test-{}", std::process::id()));
+    std::env::set_var("XDG_DATA_HOME", &data_dir);
+
+    let sans = ["127.0.0.1".to_string()];
+    let (cert_der, key_der) = certgen::generate(certgen::KEY_TYPE_ECDSA, 0, &sans, 1).unwrap();
+    let fingerprint = rclip_config::certs::fingerprint(&cert_der);
+
+    let server = RclipServer::builder()
+        .tls(ServerTls::from_der(vec![cert_der], key_der))
+        .listener(ListenerOptions::new("127.0.0.1", 0))
+        .max_size(MAX_SIZE)
+        .spawn()
+        .await
+        .unwrap();
+
+    let port = match server.local_addrs() {
+        [LocalAddr::Tcp(addr)] => addr.port(),
+        _ => panic!("Expected a single TCP listener."),
+    };
+    let client = RclipClient::connect(client_config(port, &fingerprint)).unwrap();
+
+    let mut changes = server.clipboard().subscribe();
+    let client_copy = client.clone();
+    tokio::task::spawn_blocking(move || client_copy.write("some text")).await.unwrap().unwrap();
+
+    changes.changed().await.unwrap();
+    assert_eq!(&changes.borrow_and_update()[..], b"some text");
+
+    let expected_fingerprint = fingerprint.clone();
+    let client_copy = client.clone();
+    tokio::task::spawn_blocking(move || {
+        assert_eq!(client_copy.read().unwrap(), "some text");
+
+        client_copy.clear().unwrap();
+        assert_eq!(client_copy.read().unwrap(), "");
+
+        let status = client_copy.status().unwrap();
+        assert_eq!(rclip_client_lib::status_value(&status, "fingerprint"), Some(expected_fingerprint.as_str()));
+
+        let too_large = "x".repeat(MAX_SIZE);
+        assert!(matches!(client_copy.write(&too_large), Err(ClientError::Limit(_))));
+    })
+    .await
+    .unwrap();
+
+    server.stop();
+    server.wait().await.unwrap();
+
+    let ret = tokio::task::spawn_blocking(move || client.read()).await.unwrap();
+    assert!(matches!(ret, Err(ClientError::Connection(_))));
+
+    let _ = std::fs::remove_dir_all(data_dir);
+}
+
+// Regular files can't be polled, so that a device listener on one fails right after starting.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn failure_of_any_listener_stops_the_server() {
+    let device_path = std::env::temp_dir().join(format!("rclip-server-test-device-{}", std::process::id()));
+    std::fs::write(&device_path, b"").unwrap();
+
+    let sans = ["127.0.0.1".to_string()];
+    let (cert_der, key_der) = certgen::generate(certgen::KEY_TYPE_ECDSA, 0, &sans, 1).unwrap();
+    let device_address = format!("{}{}", rclip_config::DEVICE_PREFIX, device_path.display());
+
+    let server = RclipServer::builder()
+        .tls(ServerTls::from_der(vec![cert_der], key_der))
+        .listener(ListenerOptions::new("127.0.0.1", 0))
+        .listener(ListenerOptions::new(&device_address, 0))
+        .spawn()
+        .await
+        .unwrap();
+
+    let addr = match server.local_addrs() {
+        [LocalAddr::Tcp(addr), LocalAddr::Device(_)] => *addr,
+        _ => panic!("Expected a TCP listener and a device listener."),
+    };
+
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(10), server.wait()).await;
+    assert!(matches!(ret, Ok(Err(_))), "The failure of the second listener isn't reported.");
+    assert!(std::net::TcpStream::connect(addr).is_err());
+
+    let _ = std::fs::remove_file(device_path);
+}
@@ -0,0 +1,57 @@
+#![cfg(unix)]
+
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+// Runs a plain text session on standard input, written in chunks with pauses in between.
+fn session(home: &Path, chunks: &[&[u8]]) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rclip-server"))
+        .args(["--stdio", "--no-tls"])
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_DATA_HOME", home.join("data"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+
+    for chunk in chunks {
+        stdin.write_all(chunk).unwrap();
+        stdin.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    drop(stdin);
+
+    let mut response = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut response).unwrap();
+    child.wait().unwrap();
+
+    response
+}
+
+#[test]
+fn reassembles_chunked_requests() {
+    let home = std::env::temp_dir().join(format!("rclip-server-test-stdio-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&home);
+
+    let response = session(&home, &[b"LENG", b"TH:17\nWRITE:", b"hello", b" world"]);
+    assert_eq!(response, "SUCCESS:");
+    assert_eq!(session(&home, &[b"LENGTH:5\nREAD:"]), "SUCCESS:hello world");
+
+    // Cut short by the end of the input
+    let response = session(&home, &[b"LENGTH:20\nWRITE:", b"hello"]);
+    assert!(response.starts_with("ERROR:protocol:"), "Unexpected response '{}'.", response);
+    assert_eq!(session(&home, &[b"LENGTH:5\nREAD:"]), "SUCCESS:hello world");
+
+    // Without a length header
+    let response = session(&home, &[b"WRITE:hello"]);
+    assert!(response.starts_with("ERROR:protocol:"), "Unexpected response '{}'.", response);
+
+    let _ = std::fs::remove_dir_all(&home);
+}
@@ -0,0 +1,92 @@
+#![cfg(unix)]
+
+use rclip_server::{certgen, systemd, ListenerOptions, LocalAddr, RclipServer, ServerTls};
+use socket2::{Domain, SockAddr, Socket, Type};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+fn server_tls() -> ServerTls {
+    let sans = ["127.0.0.1".to_string()];
+    let (cert_der, key_der) = certgen::generate(certgen::KEY_TYPE_ECDSA, 0, &sans, 1).unwrap();
+
+    ServerTls::from_der(vec![cert_der], key_der)
+}
+
+fn listening_socket(domain: Domain, addr: &SockAddr) -> Socket {
+    let socket = Socket::new(domain, Type::STREAM, None).unwrap();
+    socket.bind(addr).unwrap();
+    socket.listen(16).unwrap();
+
+    socket
+}
+
+fn receive(notify_socket: &UnixDatagram) -> String {
+    let mut buf = [0; 256];
+    let len = notify_socket.recv(&mut buf).unwrap();
+
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_readiness_and_watchdog_notifications() {
+    let path = std::env::temp_dir().join(format!("rclip-server-test-notify-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let notify_socket = UnixDatagram::bind(&path).unwrap();
+    notify_socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    std::env::set_var("NOTIFY_SOCKET", &path);
+
+    systemd::notify_ready("Serving");
+    let message = receive(&notify_socket);
+    assert!(message.lines().any(|line| line == "READY=1"), "Unexpected notification '{}'.", message);
+    assert!(message.lines().any(|line| line == "STATUS=Serving"), "Unexpected notification '{}'.", message);
+
+    systemd::spawn_watchdog(Duration::from_millis(10));
+    let message = receive(&notify_socket);
+    assert_eq!(message.trim_end(), "WATCHDOG=1");
+
+    std::env::remove_var("NOTIFY_SOCKET");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_passed_sockets_with_their_listener_settings() {
+    let path = std::env::temp_dir().join(format!("rclip-server-test-activated-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let unix_socket = listening_socket(Domain::UNIX, &SockAddr::unix(&path).unwrap());
+    let tcp_socket = listening_socket(Domain::IPV4, &"127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into());
+    let tcp_addr = tcp_socket.local_addr().unwrap().as_socket().unwrap();
+    let unix_host = format!("{}{}", rclip_config::UNIX_PREFIX, path.display());
+
+    let server = RclipServer::builder()
+        .tls(server_tls())
+        .listener(ListenerOptions::new(&unix_host, 0).require_token(false))
+        .listener(ListenerOptions::new("127.0.0.1", tcp_addr.port().into()))
+        .activated_listeners(vec![tcp_socket, unix_socket])
+        .spawn()
+        .await
+        .unwrap();
+
+    match server.local_addrs() {
+        [LocalAddr::Tcp(addr), LocalAddr::Unix(unix_path)] => {
+            assert_eq!(*addr, tcp_addr);
+            assert_eq!(unix_path, &path.display().to_string());
+        }
+        _ => panic!("Expected the passed TCP and Unix sockets."),
+    }
+
+    server.stop();
+    server.wait().await.unwrap();
+
+    // With several listeners, a passed socket must match one of them
+    let tcp_socket = listening_socket(Domain::IPV4, &"127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap().into());
+    let ret = RclipServer::builder()
+        .tls(server_tls())
+        .listener(ListenerOptions::new(&unix_host, 0))
+        .listener(ListenerOptions::new("127.0.0.1", 1))
+        .activated_listeners(vec![tcp_socket])
+        .spawn()
+        .await;
+    assert!(ret.is_err());
+
+    let _ = std::fs::remove_file(&path);
+}